@@ -6,6 +6,7 @@ use criterion::{ criterion_main, criterion_group, Criterion, black_box };
 const N: usize = 500;
 
 fn bench_thread_local(c: &mut Criterion) {
+    #[allow(clippy::missing_const_for_thread_local)]
     c.bench_function("std::thread_local!", |b| {
         thread_local!{
             static TL: u64 = 0x42;
@@ -17,7 +18,7 @@ fn bench_thread_local(c: &mut Criterion) {
                 .map(|_| {
                     let start = Instant::now();
                     for _ in 0..N {
-                        TL.with(|val| drop(black_box(val)));
+                        TL.with(|val| { black_box(val); });
                     }
                     start.elapsed()
                 })
@@ -46,6 +47,27 @@ fn bench_thread_local(c: &mut Criterion) {
         });
     });
 
+    // `tests/fast_path.rs` fails if it falls behind reusing a token.
+    c.bench_function("per-thread-object/token-per-access", |b| {
+        use per_thread_object::ThreadLocal;
+
+        let tl: ThreadLocal<u64> = ThreadLocal::new();
+
+        b.iter_custom(|iters| {
+            (0..iters)
+                .into_par_iter()
+                .map(|_| {
+                    let start = Instant::now();
+                    for _ in 0..N {
+                        per_thread_object::stack_token!(token);
+                        black_box(*tl.get_or_init(token, || 0x42));
+                    }
+                    start.elapsed()
+                })
+                .sum()
+        });
+    });
+
     c.bench_function("thread_local", |b| {
         use thread_local::ThreadLocal;
 
@@ -77,7 +99,7 @@ fn bench_thread_local(c: &mut Criterion) {
                 .map(|_| {
                     let start = Instant::now();
                     for _ in 0..N {
-                        tl.with(|val| drop(black_box(val)));
+                        tl.with(|val| { black_box(val); });
                     }
                     start.elapsed()
                 })
@@ -0,0 +1,47 @@
+//! Allocator of storage and pages.
+//!
+//! With `allocator-api2` feature, any `allocator_api2::alloc::Allocator` can be used,
+//! otherwise only the global allocator is available.
+
+#[cfg(feature = "allocator-api2")]
+pub use allocator_api2::alloc::{ Allocator, Global };
+
+#[cfg(not(feature = "allocator-api2"))]
+pub use self::inner::{ Allocator, Global };
+
+
+#[cfg(not(feature = "allocator-api2"))]
+mod inner {
+    use alloc::alloc::{ self as global, Layout };
+    use core::ptr::NonNull;
+
+    /// Minimal allocator trait, only implemented by [`Global`].
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe trait Allocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+    }
+
+    pub struct AllocError;
+
+    /// The global memory allocator.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Global;
+
+    unsafe impl Allocator for Global {
+        #[inline]
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            // storage layout always contains a header, so it is never zero-sized.
+            debug_assert_ne!(layout.size(), 0);
+
+            let ptr = NonNull::new(unsafe { global::alloc(layout) }).ok_or(AllocError)?;
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+
+        #[inline]
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            global::dealloc(ptr.as_ptr(), layout)
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use core::{ fmt, mem };
+use core::marker::PhantomData;
+use alloc::boxed::Box;
+use crossbeam_utils::CachePadded;
+use crate::page::{ Storage, page_len };
+use crate::util::BoxTail;
+use crate::allocator::{ Allocator, Global };
+use crate::{ ThreadLocal, EvictionPolicy, ThreadIdentity, OsThread };
+
+
+/// Layout of slots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    /// Align each slot to cache line, so that threads never false-share.
+    CacheLine,
+
+    /// Pack slots densely, this saves memory for small values.
+    None
+}
+
+/// Configuration of `ThreadLocal`, see [`ThreadLocal::builder`].
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use per_thread_object::{ ThreadLocal, Padding };
+///
+/// let tl: ThreadLocal<u8> = ThreadLocal::builder()
+///     .threads(64)
+///     .padding(Padding::None)
+///     .init(|| 0x42)
+///     .try_build()
+///     .unwrap();
+///
+/// assert_eq!(64, tl.stats().capacity);
+///
+/// per_thread_object::stack_token!(token);
+/// assert_eq!(0x42, *tl.get_or_create(token));
+/// ```
+pub struct Builder<T, A = Global, Id = OsThread> {
+    config: Config,
+    init: Option<Init<T>>,
+    overflow: Option<T>,
+    alloc: A,
+    identity: PhantomData<fn() -> Id>
+}
+
+pub(crate) type Init<T> = Box<dyn Fn() -> T + Send + Sync>;
+
+pub(crate) struct Config {
+    pub capacity: usize,
+    pub page_size: Option<usize>,
+    pub padding: Padding,
+    pub eviction: Option<EvictionPolicy>,
+    pub max_threads: Option<usize>,
+    pub shrink_on_exit: bool
+}
+
+/// Error of [`Builder::try_build`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// Number of lock-free threads is zero.
+    ZeroCapacity,
+
+    /// Size of fallback page is zero.
+    ZeroPageSize,
+
+    /// `MaxLive` eviction policy allows no live value.
+    ZeroMaxLive,
+
+    /// Size of storage overflows.
+    CapacityOverflow,
+
+    /// Memory allocator failed to allocate storage.
+    AllocFailed,
+
+    /// Max number of threads is zero.
+    ZeroMaxThreads
+}
+
+/// Error returned when `max_threads` threads already hold a value,
+/// see [`Builder::max_threads`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapacityExceeded;
+
+impl Padding {
+    pub(crate) fn align(self) -> usize {
+        match self {
+            Padding::CacheLine => mem::align_of::<CachePadded<u8>>(),
+            Padding::None => 1
+        }
+    }
+}
+
+impl Default for Padding {
+    #[inline]
+    fn default() -> Padding {
+        Padding::CacheLine
+    }
+}
+
+impl Config {
+    pub fn new(capacity: usize) -> Config {
+        Config {
+            capacity,
+            page_size: None,
+            padding: Padding::default(),
+            eviction: None,
+            max_threads: None,
+            shrink_on_exit: false
+        }
+    }
+
+    /// Check configuration for storage whose slots are `S`.
+    ///
+    /// Returns the number of fallback pages that can be allocated.
+    pub fn validate<S>(&self) -> Result<usize, BuildError> {
+        if self.capacity == 0 {
+            return Err(BuildError::ZeroCapacity);
+        }
+
+        if self.page_size == Some(0) {
+            return Err(BuildError::ZeroPageSize);
+        }
+
+        if self.eviction == Some(EvictionPolicy::MaxLive(0)) {
+            return Err(BuildError::ZeroMaxLive);
+        }
+
+        if self.max_threads == Some(0) {
+            return Err(BuildError::ZeroMaxThreads);
+        }
+
+        // fallback pages double in size, stop at the first one that overflows.
+        let page_size = self.page_size.unwrap_or(self.capacity);
+        let align = self.padding.align();
+        let max_pages = (1..=usize::BITS as usize)
+            .take_while(|&page_id| {
+                page_len(page_size, page_id)
+                    .is_some_and(|len| BoxTail::<(), S>::layout(len, align).is_ok())
+            })
+            .count();
+
+        if max_pages == 0 {
+            return Err(BuildError::CapacityOverflow);
+        }
+
+        Ok(max_pages)
+    }
+}
+
+impl<T: Send + 'static> Builder<T> {
+    pub(crate) fn new() -> Builder<T> {
+        Builder {
+            config: Config::new(crate::default_threads()),
+            init: None,
+            overflow: None,
+            alloc: Global,
+            identity: PhantomData
+        }
+    }
+}
+
+impl<T: Send + 'static, A: Allocator + Clone, Id: ThreadIdentity> Builder<T, A, Id> {
+    /// Allocator of slots, used for the lock-free array and all fallback pages.
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use per_thread_object::{ ThreadLocal, Global };
+    ///
+    /// let tl: ThreadLocal<u8, &Global> = ThreadLocal::builder()
+    ///     .allocator(&Global)
+    ///     .build();
+    /// # drop(tl);
+    /// ```
+    #[cfg(feature = "allocator-api2")]
+    pub fn allocator<B: Allocator + Clone>(self, alloc: B) -> Builder<T, B, Id> {
+        Builder {
+            config: self.config,
+            init: self.init,
+            overflow: self.overflow,
+            alloc,
+            identity: PhantomData
+        }
+    }
+
+    /// Provider of current execution context, default is [`OsThread`].
+    ///
+    /// Values follow the [`Context`](crate::Context) given by `J`,
+    /// e.g. a fiber that migrates between OS threads.
+    pub fn identity<J: ThreadIdentity>(self) -> Builder<T, A, J> {
+        Builder {
+            config: self.config,
+            init: self.init,
+            overflow: self.overflow,
+            alloc: self.alloc,
+            identity: PhantomData
+        }
+    }
+
+    /// Number of lock-free threads, must be greater than zero.
+    pub fn threads(mut self, num: usize) -> Builder<T, A, Id> {
+        self.config.capacity = num;
+        self
+    }
+
+    /// Number of slots in first fallback page, must be greater than zero.
+    ///
+    /// Each next page is twice as large as the previous one.
+    /// Default is same as the number of lock-free threads.
+    pub fn page_size(mut self, size: usize) -> Builder<T, A, Id> {
+        self.config.page_size = Some(size);
+        self
+    }
+
+    /// Layout of slots, applied to both the lock-free array and fallback pages.
+    ///
+    /// Default is `Padding::CacheLine`.
+    pub fn padding(mut self, padding: Padding) -> Builder<T, A, Id> {
+        self.config.padding = padding;
+        self
+    }
+
+    /// Policy for evicting idle values, see [`ThreadLocal::with_eviction`].
+    pub fn eviction(mut self, policy: EvictionPolicy) -> Builder<T, A, Id> {
+        self.config.eviction = Some(policy);
+        self
+    }
+
+    /// Max number of threads that can hold a value at the same time.
+    ///
+    /// Once reached, [`try_get_or_init_capped`](ThreadLocal::try_get_or_init_capped)
+    /// returns [`InitError::Capacity`](crate::InitError::Capacity) for other threads
+    /// and `get_or_init` panics, unless an [`overflow`](Builder::overflow) value is set.
+    pub fn max_threads(mut self, num: usize) -> Builder<T, A, Id> {
+        self.config.max_threads = Some(num);
+        self
+    }
+
+    /// Value shared by threads that exceed `max_threads`.
+    ///
+    /// `get` still returns `None` for these threads.
+    pub fn overflow(mut self, value: T) -> Builder<T, A, Id>
+    where
+        T: Sync
+    {
+        self.overflow = Some(value);
+        self
+    }
+
+    /// Free trailing fallback pages automatically when the threads using them exit.
+    ///
+    /// Default is `false`, see [`ThreadLocal::shrink_to_fit`].
+    pub fn shrink_on_exit(mut self, enable: bool) -> Builder<T, A, Id> {
+        self.config.shrink_on_exit = enable;
+        self
+    }
+
+    /// Initializer used by [`ThreadLocal::get_or_create`].
+    pub fn init<F>(mut self, init: F) -> Builder<T, A, Id>
+    where
+        F: Fn() -> T + Send + Sync + 'static
+    {
+        self.init = Some(Box::new(init));
+        self
+    }
+
+    /// Build `ThreadLocal`.
+    ///
+    /// Panics if configuration is invalid or allocation failed.
+    pub fn build(self) -> ThreadLocal<T, A, Id> {
+        match self.try_build() {
+            Ok(tl) => tl,
+            Err(err) => panic!("failed to build `ThreadLocal`: {}", err)
+        }
+    }
+
+    pub fn try_build(self) -> Result<ThreadLocal<T, A, Id>, BuildError> {
+        Ok(ThreadLocal {
+            pool: Storage::try_new_in(&self.config, self.alloc)?,
+            init: self.init,
+            overflow: self.overflow,
+            _identity: PhantomData
+        })
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BuildError::ZeroCapacity => "number of threads must be greater than zero",
+            BuildError::ZeroPageSize => "page size must be greater than zero",
+            BuildError::ZeroMaxLive => "max live values must be greater than zero",
+            BuildError::CapacityOverflow => "capacity overflow",
+            BuildError::AllocFailed => "memory allocation failed",
+            BuildError::ZeroMaxThreads => "max threads must be greater than zero"
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+impl fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("too many threads hold a thread-local value")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CapacityExceeded {}
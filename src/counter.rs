@@ -0,0 +1,165 @@
+use crate::ThreadLocal;
+use crate::loom::sync::Arc;
+use crate::loom::sync::atomic::{ AtomicU64, Ordering };
+
+
+/// Sharded counter
+///
+/// Each thread increments its own slot,
+/// so `add` never contends with other threads.
+/// `sum` reads all slots, plus the total of the threads that have exited.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use std::thread;
+/// use std::sync::Arc;
+/// use per_thread_object::PerThreadCounter;
+///
+/// let counter = Arc::new(PerThreadCounter::new());
+/// let counter2 = counter.clone();
+///
+/// thread::spawn(move || counter2.add(2))
+///     .join()
+///     .unwrap();
+///
+/// counter.inc();
+/// assert_eq!(3, counter.sum());
+/// ```
+pub struct PerThreadCounter {
+    shards: Shards
+}
+
+/// Sharded gauge
+///
+/// Same as [`PerThreadCounter`], but can be decreased.
+pub struct PerThreadGauge {
+    shards: Shards
+}
+
+struct Shards {
+    local: ThreadLocal<Shard>,
+    retired: Arc<AtomicU64>
+}
+
+struct Shard {
+    value: AtomicU64,
+    retired: Arc<AtomicU64>
+}
+
+impl Shards {
+    fn new(local: ThreadLocal<Shard>) -> Shards {
+        Shards {
+            local,
+            retired: Arc::new(AtomicU64::new(0))
+        }
+    }
+
+    #[inline]
+    fn add(&self, n: u64) {
+        crate::stack_token!(token);
+
+        let shard = self.local.get_or_init(token, || Shard {
+            value: AtomicU64::new(0),
+            retired: self.retired.clone()
+        });
+
+        // only the owner thread writes its shard,
+        // so we do not need read-modify-write here.
+        let val = shard.value.load(Ordering::Relaxed);
+        shard.value.store(val.wrapping_add(n), Ordering::Relaxed);
+    }
+
+    fn sum(&self) -> u64 {
+        // exiting thread moves its value to `retired` while the thread list is locked,
+        // so it must be read inside `with_iter` to avoid counting it twice.
+        self.local.with_iter(|iter| {
+            iter.fold(self.retired.load(Ordering::Relaxed), |sum, (_, shard)| {
+                sum.wrapping_add(shard.value.load(Ordering::Relaxed))
+            })
+        })
+    }
+}
+
+impl Drop for Shard {
+    fn drop(&mut self) {
+        let val = self.value.load(Ordering::Relaxed);
+        self.retired.fetch_add(val, Ordering::Relaxed);
+    }
+}
+
+impl PerThreadCounter {
+    pub fn new() -> PerThreadCounter {
+        PerThreadCounter {
+            shards: Shards::new(ThreadLocal::new())
+        }
+    }
+
+    pub fn with_threads(num: usize) -> PerThreadCounter {
+        PerThreadCounter {
+            shards: Shards::new(ThreadLocal::with_threads(num))
+        }
+    }
+
+    #[inline]
+    pub fn add(&self, n: u64) {
+        self.shards.add(n)
+    }
+
+    #[inline]
+    pub fn inc(&self) {
+        self.shards.add(1)
+    }
+
+    /// Sum of all threads.
+    ///
+    /// Increments that race with `sum` may or may not be counted.
+    pub fn sum(&self) -> u64 {
+        self.shards.sum()
+    }
+}
+
+impl PerThreadGauge {
+    pub fn new() -> PerThreadGauge {
+        PerThreadGauge {
+            shards: Shards::new(ThreadLocal::new())
+        }
+    }
+
+    pub fn with_threads(num: usize) -> PerThreadGauge {
+        PerThreadGauge {
+            shards: Shards::new(ThreadLocal::with_threads(num))
+        }
+    }
+
+    #[inline]
+    pub fn add(&self, n: i64) {
+        // two's complement, so wrapping add of unsigned is the same as signed.
+        self.shards.add(n as u64)
+    }
+
+    #[inline]
+    pub fn sub(&self, n: i64) {
+        self.shards.add(n.wrapping_neg() as u64)
+    }
+
+    /// Sum of all threads.
+    ///
+    /// Changes that race with `sum` may or may not be counted.
+    pub fn sum(&self) -> i64 {
+        self.shards.sum() as i64
+    }
+}
+
+impl Default for PerThreadCounter {
+    #[inline]
+    fn default() -> PerThreadCounter {
+        PerThreadCounter::new()
+    }
+}
+
+impl Default for PerThreadGauge {
+    #[inline]
+    fn default() -> PerThreadGauge {
+        PerThreadGauge::new()
+    }
+}
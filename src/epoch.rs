@@ -0,0 +1,284 @@
+//! Epoch-based memory reclamation.
+//!
+//! Unlike `crossbeam-epoch`, there is no global collector.
+//! Each [`Domain`] keeps its own participants in per-object thread-local storage,
+//! so independent lock-free structures do not block each other's reclamation.
+//!
+//! ```rust
+//! # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+//! use std::sync::atomic::{ AtomicPtr, Ordering };
+//! use per_thread_object::epoch::Domain;
+//!
+//! let domain = Domain::new();
+//! let ptr = AtomicPtr::new(Box::into_raw(Box::new(1)));
+//!
+//! per_thread_object::stack_token!(token);
+//!
+//! let guard = domain.pin(token);
+//! let old = ptr.swap(Box::into_raw(Box::new(2)), Ordering::AcqRel);
+//!
+//! unsafe {
+//!     guard.defer_destroy(old);
+//! }
+//! # drop(guard);
+//! # drop(domain);
+//! # drop(unsafe { Box::from_raw(ptr.into_inner()) });
+//! ```
+
+use core::mem;
+use core::marker::PhantomData;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use crate::{ ThreadLocal, StackToken };
+use crate::loom::cell::UnsafeCell;
+use crate::loom::sync::{ Arc, Mutex };
+use crate::loom::sync::atomic::{ self, AtomicUsize, Ordering };
+
+
+/// Number of deferred functions before trying to collect.
+const COLLECT_THRESHOLD: usize = 64;
+
+/// Epoch is always even, the lowest bit of participant state marks pinned.
+const PINNED: usize = 1;
+const EPOCH_STEP: usize = 2;
+
+/// Reclamation domain
+///
+/// Garbage deferred in a domain is executed when no thread pinned in the domain
+/// can still reach it, or when domain is dropped.
+/// Garbage left by exiting threads is handed to the domain.
+pub struct Domain {
+    participants: ThreadLocal<Participant>,
+    global: Arc<Global>
+}
+
+/// Guard that keeps current thread pinned.
+pub struct Guard<'a> {
+    domain: &'a Domain,
+    local: &'a Participant,
+    _marker: PhantomData<*const ()>
+}
+
+struct Global {
+    epoch: AtomicUsize,
+    orphans: Mutex<Vec<Deferred>>
+}
+
+struct Participant {
+    state: AtomicUsize,
+    guards: AtomicUsize,
+    bag: UnsafeCell<Vec<Deferred>>,
+    global: Arc<Global>
+}
+
+struct Deferred {
+    epoch: usize,
+    f: Box<dyn FnOnce() + Send>
+}
+
+struct SendPtr<T>(*mut T);
+
+impl Domain {
+    pub fn new() -> Domain {
+        Domain::from_local(ThreadLocal::new())
+    }
+
+    pub fn with_threads(num: usize) -> Domain {
+        Domain::from_local(ThreadLocal::with_threads(num))
+    }
+
+    fn from_local(participants: ThreadLocal<Participant>) -> Domain {
+        Domain {
+            participants,
+            global: Arc::new(Global {
+                epoch: AtomicUsize::new(0),
+                orphans: Mutex::new(Vec::new())
+            })
+        }
+    }
+
+    /// Pin current thread.
+    ///
+    /// Pinning is reentrant, thread is unpinned when last guard is dropped.
+    pub fn pin<'a>(&'a self, token: &'a StackToken) -> Guard<'a> {
+        let local = self.participants.get_or_init(token, || Participant {
+            state: AtomicUsize::new(0),
+            guards: AtomicUsize::new(0),
+            bag: UnsafeCell::new(Vec::new()),
+            global: self.global.clone()
+        });
+
+        let guards = local.guards.load(Ordering::Relaxed);
+        local.guards.store(guards + 1, Ordering::Relaxed);
+
+        if guards == 0 {
+            let epoch = self.global.epoch.load(Ordering::Relaxed);
+            local.state.store(epoch | PINNED, Ordering::Relaxed);
+            atomic::fence(Ordering::SeqCst);
+        }
+
+        Guard {
+            domain: self,
+            local,
+            _marker: PhantomData
+        }
+    }
+
+    /// Try to advance global epoch.
+    ///
+    /// Epoch can only be advanced if all pinned threads have observed current epoch.
+    fn try_advance(&self) -> usize {
+        let epoch = self.global.epoch.load(Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+
+        let observed = self.participants.with_iter(|mut iter| {
+            iter.all(|(_, local)| {
+                let state = local.state.load(Ordering::Relaxed);
+                state & PINNED == 0 || state & !PINNED == epoch
+            })
+        });
+
+        if !observed {
+            return epoch;
+        }
+
+        atomic::fence(Ordering::Acquire);
+
+        let new_epoch = epoch.wrapping_add(EPOCH_STEP);
+        match self.global.epoch.compare_exchange(epoch, new_epoch, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => new_epoch,
+            Err(epoch) => epoch
+        }
+    }
+}
+
+impl Guard<'_> {
+    /// Defer `f` until no pinned thread can observe the garbage it releases.
+    pub fn defer<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static
+    {
+        self.defer_boxed(Box::new(f))
+    }
+
+    /// Defer dropping the `Box` that `ptr` came from.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`,
+    /// and must be unreachable for threads that pin after this call.
+    pub unsafe fn defer_destroy<T: Send + 'static>(&self, ptr: *mut T) {
+        let ptr = SendPtr(ptr);
+        self.defer(move || {
+            let ptr = ptr;
+            drop(Box::from_raw(ptr.0));
+        })
+    }
+
+    /// Try to advance epoch and execute expired garbage.
+    pub fn flush(&self) {
+        let epoch = self.domain.try_advance();
+
+        let expired = self.local.bag.with_mut(|bag| {
+            let bag = unsafe { &mut *bag };
+            take_expired(bag, epoch)
+        });
+        expired.into_iter().for_each(Deferred::call);
+
+        let expired = {
+            let mut orphans = self.domain.global.orphans.lock().unwrap();
+            take_expired(&mut orphans, epoch)
+        };
+        expired.into_iter().for_each(Deferred::call);
+    }
+
+    fn defer_boxed(&self, f: Box<dyn FnOnce() + Send>) {
+        atomic::fence(Ordering::SeqCst);
+        let epoch = self.domain.global.epoch.load(Ordering::Relaxed);
+
+        // # Safety
+        //
+        // bag is only accessed by owner thread.
+        let len = self.local.bag.with_mut(|bag| {
+            let bag = unsafe { &mut *bag };
+            bag.push(Deferred { epoch, f });
+            bag.len()
+        });
+
+        if len % COLLECT_THRESHOLD == 0 {
+            self.flush();
+        }
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        let guards = self.local.guards.load(Ordering::Relaxed) - 1;
+        self.local.guards.store(guards, Ordering::Relaxed);
+
+        if guards == 0 {
+            let state = self.local.state.load(Ordering::Relaxed);
+            self.local.state.store(state & !PINNED, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Participant {
+    fn drop(&mut self) {
+        let bag = self.bag.with_mut(|bag| mem::take(unsafe { &mut *bag }));
+
+        if !bag.is_empty() {
+            self.global.orphans.lock()
+                .unwrap()
+                .extend(bag);
+        }
+    }
+}
+
+impl Drop for Global {
+    fn drop(&mut self) {
+        // all participants have been dropped, no thread can be pinned.
+        let orphans = {
+            let mut orphans = self.orphans.lock().unwrap();
+            mem::take(&mut *orphans)
+        };
+
+        orphans.into_iter().for_each(Deferred::call);
+    }
+}
+
+impl Deferred {
+    fn call(self) {
+        (self.f)()
+    }
+}
+
+impl Default for Domain {
+    #[inline]
+    fn default() -> Domain {
+        Domain::new()
+    }
+}
+
+// # Safety
+//
+// bag is only accessed by owner thread, or when participant is dropped.
+unsafe impl Sync for Participant {}
+
+unsafe impl<T: Send> Send for SendPtr<T> {}
+
+/// Garbage deferred in `epoch` is expired when global epoch advanced twice.
+fn take_expired(bag: &mut Vec<Deferred>, epoch: usize) -> Vec<Deferred> {
+    let mut expired = Vec::new();
+    let mut i = 0;
+
+    while i < bag.len() {
+        if epoch.wrapping_sub(bag[i].epoch) >= 2 * EPOCH_STEP {
+            expired.push(bag.swap_remove(i));
+        } else {
+            i += 1;
+        }
+    }
+
+    expired
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{ AtomicBool, Ordering };
+use crate::thread;
+
+
+static LEAK_ORPHANS: AtomicBool = AtomicBool::new(false);
+
+/// What to do with the values of threads that did not survive `fork`,
+/// see [`set_orphan_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// Drop the values in the child, which is the default.
+    #[default]
+    Drop,
+
+    /// Forget the values without running their destructors,
+    /// for values that own resources shared with the parent.
+    Leak
+}
+
+/// Set how [`after_fork_child`] handles the values of threads that did not survive `fork`.
+pub fn set_orphan_policy(policy: OrphanPolicy) {
+    LEAK_ORPHANS.store(policy == OrphanPolicy::Leak, Ordering::Relaxed);
+}
+
+/// Reset thread state in the child process after `fork`.
+///
+/// Only the thread that called `fork` survives in the child.
+/// The ids of other threads are returned to the pool,
+/// and their values are dropped or leaked according to [`OrphanPolicy`].
+///
+/// With `fork` feature, this is called automatically by a `pthread_atfork` handler.
+/// `fork` then also waits for destructors of thread-local values
+/// and closures of [`PerCpu::with`](crate::PerCpu::with), because they run with a lock held.
+/// They must not wait for a thread that may call `fork`, otherwise both wait forever.
+///
+/// Without `fork` feature, locks are not reinitialized,
+/// so it is only valid if no thread held a lock of this crate when `fork` was called,
+/// e.g. other threads were idle or blocked outside of this crate.
+///
+/// # Safety
+///
+/// It must be called in the child process before any other use of this crate,
+/// and no other thread could use this crate when `fork` was called,
+/// which `fork` feature guarantees by making `fork` wait for them.
+pub unsafe fn after_fork_child() {
+    thread::reap_orphans(LEAK_ORPHANS.load(Ordering::Relaxed));
+}
+
+/// Register `pthread_atfork` handlers, once per process.
+#[cfg(feature = "fork")]
+#[cfg(not(any(feature = "loom", feature = "shuttle")))]
+pub fn register() {
+    use std::sync::Once;
+    use crate::loom::fork;
+
+    static REGISTER: Once = Once::new();
+
+    extern "C" fn prepare() {
+        fork::prepare();
+    }
+
+    extern "C" fn parent() {
+        fork::release();
+    }
+
+    extern "C" fn child() {
+        fork::release();
+
+        // # Safety
+        //
+        // `prepare` waited until no other thread held a lock.
+        unsafe {
+            after_fork_child();
+        }
+    }
+
+    REGISTER.call_once(|| {
+        let ret = unsafe { libc::pthread_atfork(Some(prepare), Some(parent), Some(child)) };
+        assert_eq!(ret, 0, "failed to register fork handlers");
+    });
+}
+
+#[cfg(feature = "fork")]
+#[cfg(any(feature = "loom", feature = "shuttle"))]
+pub fn register() {}
@@ -0,0 +1,252 @@
+//! Thread-local values inherited by spawned threads.
+//!
+//! Threads spawned by [`spawn`] or [`Builder`] start with values
+//! derived from the values of their parent thread.
+//!
+//! ```rust
+//! # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+//! use per_thread_object::inherit::{ self, InheritableThreadLocal };
+//!
+//! let ctx: &'static InheritableThreadLocal<String> =
+//!     Box::leak(Box::new(InheritableThreadLocal::new(|parent| format!("{}/child", parent))));
+//!
+//! per_thread_object::stack_token!(token);
+//! ctx.get_or_init(token, || String::from("request"));
+//!
+//! inherit::spawn(move || {
+//!     per_thread_object::stack_token!(token);
+//!     assert_eq!("request/child", ctx.get(token).unwrap());
+//! })
+//!     .join()
+//!     .unwrap();
+//! ```
+
+use std::{ io, thread };
+use std::cell::RefCell;
+use std::sync::{ Arc, Weak };
+use crate::{ ThreadLocal, StackToken };
+
+#[cfg(feature = "loom")]
+use loom::thread_local;
+
+#[cfg(feature = "shuttle")]
+use shuttle::thread_local;
+
+
+thread_local!{
+    /// Inheritable storages in which current thread has a value.
+    static INHERITABLE: RefCell<Vec<Weak<dyn Inherit>>> = RefCell::new(Vec::new());
+}
+
+/// Per-object thread-local storage whose values are inherited by child threads.
+///
+/// The value of child is built by `inherit` from the value of its parent,
+/// when the child is spawned by this module.
+/// Threads spawned in other ways start without value.
+pub struct InheritableThreadLocal<T: Send + 'static> {
+    inner: Arc<Inheritable<T>>
+}
+
+struct Inheritable<T: Send + 'static> {
+    local: ThreadLocal<T>,
+    inherit: fn(&T) -> T
+}
+
+trait Inherit: Send + Sync {
+    fn capture(self: Arc<Self>) -> Option<Box<dyn FnOnce() + Send>>;
+}
+
+/// Values captured from parent thread.
+pub struct Inherited {
+    installers: Vec<Box<dyn FnOnce() + Send>>
+}
+
+/// Thread factory that passes inheritable values to child thread,
+/// same as `std::thread::Builder`.
+#[derive(Debug)]
+pub struct Builder {
+    inner: thread::Builder
+}
+
+impl<T: Send + 'static> InheritableThreadLocal<T> {
+    pub fn new(inherit: fn(&T) -> T) -> InheritableThreadLocal<T> {
+        InheritableThreadLocal::with_threads(crate::default_threads(), inherit)
+    }
+
+    pub fn with_threads(num: usize, inherit: fn(&T) -> T) -> InheritableThreadLocal<T> {
+        InheritableThreadLocal {
+            inner: Arc::new(Inheritable {
+                local: ThreadLocal::with_threads(num),
+                inherit
+            })
+        }
+    }
+
+    #[inline]
+    pub fn get<'stack>(&'stack self, token: &'stack StackToken) -> Option<&'stack T> {
+        self.inner.local.get(token)
+    }
+
+    #[inline]
+    pub fn get_or_init<'stack, F>(&'stack self, token: &'stack StackToken, init: F)
+        -> &'stack T
+    where
+        F: FnOnce() -> T
+    {
+        use std::convert::Infallible;
+
+        match self.get_or_try_init::<_, Infallible>(token, || Ok(init())) {
+            Ok(val) => val,
+            Err(err) => match err {}
+        }
+    }
+
+    #[inline]
+    pub fn get_or_try_init<'stack, F, E>(&'stack self, token: &'stack StackToken, init: F)
+        -> Result<&'stack T, E>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        match self.inner.local.get(token) {
+            Some(val) => Ok(val),
+            None => self.or_try(token, init)
+        }
+    }
+
+    #[cold]
+    fn or_try<'stack, F, E>(&'stack self, token: &'stack StackToken, init: F)
+        -> Result<&'stack T, E>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        let val = match self.inner.local.try_init(token, init) {
+            Ok(val) => val,
+            Err(err) => return Err(err.unwrap_init())
+        };
+        register(Arc::downgrade(&self.inner) as Weak<dyn Inherit>);
+        Ok(val)
+    }
+}
+
+impl<T: Send + 'static> Inherit for Inheritable<T> {
+    fn capture(self: Arc<Self>) -> Option<Box<dyn FnOnce() + Send>> {
+        crate::stack_token!(token);
+
+        let value = (self.inherit)(self.local.get(token)?);
+
+        Some(Box::new(move || {
+            crate::stack_token!(token);
+
+            self.local.get_or_init(token, || value);
+            register(Arc::downgrade(&self) as Weak<dyn Inherit>);
+        }))
+    }
+}
+
+fn register(inherit: Weak<dyn Inherit>) {
+    let _ = INHERITABLE.try_with(|list| {
+        let mut list = list.borrow_mut();
+        list.retain(|inherit| inherit.strong_count() != 0);
+        list.push(inherit);
+    });
+}
+
+impl Inherited {
+    /// Capture inheritable values of current thread.
+    pub fn capture() -> Inherited {
+        // `inherit` may initialize other inheritable storage,
+        // so we cannot borrow the list while calling it.
+        let list = INHERITABLE.try_with(|list| list.borrow().clone())
+            .unwrap_or_default();
+
+        let installers = list.into_iter()
+            .filter_map(|inherit| inherit.upgrade()?.capture())
+            .collect();
+
+        Inherited { installers }
+    }
+
+    /// Install captured values to current thread.
+    ///
+    /// Values that already exist on current thread are kept.
+    pub fn install(self) {
+        for install in self.installers {
+            install();
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder { inner: thread::Builder::new() }
+    }
+
+    pub fn name(self, name: String) -> Builder {
+        Builder { inner: self.inner.name(name) }
+    }
+
+    pub fn stack_size(self, size: usize) -> Builder {
+        Builder { inner: self.inner.stack_size(size) }
+    }
+
+    /// Spawn a thread that starts with values inherited from current thread.
+    pub fn spawn<F, R>(self, f: F) -> io::Result<thread::JoinHandle<R>>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static
+    {
+        let inherited = Inherited::capture();
+
+        self.inner.spawn(move || {
+            inherited.install();
+            f()
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/// Spawn a thread that starts with values inherited from current thread.
+///
+/// Panics if failed to create thread, same as `std::thread::spawn`.
+pub fn spawn<F, R>(f: F) -> thread::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static
+{
+    Builder::new().spawn(f).expect("failed to spawn thread")
+}
+
+/// Spawn handler for `rayon::ThreadPoolBuilder`,
+/// workers inherit values from the thread that builds the pool.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use per_thread_object::inherit;
+///
+/// let pool = rayon::ThreadPoolBuilder::new()
+///     .spawn_handler(inherit::rayon_spawn_handler)
+///     .build()
+///     .unwrap();
+/// # drop(pool);
+/// ```
+#[cfg(feature = "rayon")]
+pub fn rayon_spawn_handler(thread: rayon::ThreadBuilder) -> io::Result<()> {
+    let mut builder = Builder::new();
+
+    if let Some(name) = thread.name() {
+        builder = builder.name(name.into());
+    }
+
+    if let Some(size) = thread.stack_size() {
+        builder = builder.stack_size(size);
+    }
+
+    builder.spawn(move || thread.run())?;
+
+    Ok(())
+}
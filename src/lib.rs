@@ -29,20 +29,93 @@
 //! *tl.get_or_init(token, default).borrow_mut() += 2;
 //! assert_eq!(0x2, *tl.get_or_init(token, default).borrow());
 //! ```
+//!
+//! ## `no_std`
+//!
+//! Without the default `std` feature, the crate only depends on `alloc`.
+//! Locks spin, `EvictionPolicy::Ttl`, `PerCpu` and `inherit` are unavailable,
+//! and the runtime provides the context of each thread with `set_current_context`.
+//! It stays available with `std`, which another crate may enable,
+//! but then OS threads use the thread locals of `std` and the provider is ignored.
+//!
+//! ## `fork`
+//!
+//! With `fork` feature, `fork` waits until no thread holds a lock of this crate,
+//! and the child resets thread state, see `after_fork_child`.
+//! Every lock then also costs a thread local access and an atomic update of a counter
+//! that is shared with some other threads, which the lock-free fast path never takes.
+//! `fork` also waits for destructors of values and closures of `PerCpu::with`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 #[cfg(not(feature = "loom"))]
 mod loom;
 
 #[cfg(feature = "loom")]
+#[allow(clippy::single_component_path_imports)]
 use loom;
 
 mod util;
+mod allocator;
 mod thread;
 mod page;
+mod counter;
+mod pool;
+mod rwlock;
+mod publish;
+#[cfg(feature = "std")]
+mod percpu;
+mod scoped;
+mod builder;
+
+#[cfg(feature = "rayon")]
+mod par;
+
+#[cfg(feature = "tokio")]
+mod runtime;
+pub mod epoch;
+
+#[cfg(feature = "std")]
+pub mod inherit;
+
+#[cfg(all(unix, feature = "std"))]
+mod fork;
 
-use std::ptr::NonNull;
-use loom::cell::UnsafeCell;
-use page::Storage;
+use core::fmt;
+use core::cell::Cell;
+use core::ptr::NonNull;
+use core::marker::PhantomData;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use loom::sync::atomic::Ordering;
+use page::{ Storage, Slot, Iter };
+
+#[cfg(not(feature = "allocator-api2"))]
+use allocator::Allocator;
+
+pub use thread::{ ThreadId, ThreadStats, ThreadIdentity, OsThread, Context, set_current_context };
+
+#[cfg(all(unix, feature = "std"))]
+pub use fork::{ after_fork_child, set_orphan_policy, OrphanPolicy };
+pub use page::Stats;
+pub use counter::{ PerThreadCounter, PerThreadGauge };
+pub use pool::{ PerThreadPool, Pooled };
+pub use rwlock::{ ShardedRwLock, ShardedRwLockReadGuard, ShardedRwLockWriteGuard };
+pub use publish::PerThreadPublished;
+#[cfg(feature = "std")]
+pub use percpu::PerCpu;
+pub use scoped::ScopedThreadLocal;
+pub use builder::{ Builder, BuildError, CapacityExceeded, Padding };
+
+pub use allocator::Global;
+
+#[cfg(feature = "allocator-api2")]
+pub use allocator::Allocator;
+
+#[cfg(feature = "tokio")]
+pub use runtime::WorkerLocals;
 
 
 /// Per-object thread-local storage
@@ -56,23 +129,83 @@ use page::Storage;
 /// this crate supports any number of threads,
 /// but only the specified number of threads are lock-free.
 ///
+/// ## Allocator
+///
+/// Slots of the lock-free array and fallback pages are allocated with `A`,
+/// see `Builder::allocator` with `allocator-api2` feature.
+///
+/// ## Identity
+///
+/// Values belong to OS threads by default,
+/// see [`Builder::identity`] for fibers or other execution contexts.
+///
 /// ## Panic when dropping
 ///
 /// `ThreadLocal` will release object at the end of thread.
 /// If panic occurs during this process, it may cause a memory leak.
-pub struct ThreadLocal<T: Send + 'static> {
-    pool: Storage<T>
+pub struct ThreadLocal<T: Send + 'static, A: Allocator + Clone = Global, Id: ThreadIdentity = OsThread> {
+    pool: Storage<T, A>,
+    init: Option<builder::Init<T>>,
+    overflow: Option<T>,
+    _identity: PhantomData<fn() -> Id>
+}
+
+/// Error returned by [`ThreadLocal::try_get_or_init_capped`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitError<E> {
+    /// Initializer failed.
+    Init(E),
+
+    /// `max_threads` threads already hold a value, see [`Builder::max_threads`].
+    Capacity,
+
+    /// Id of current thread is so large that its fallback page overflows,
+    /// see [`Builder::page_size`].
+    PageOverflow
+}
+
+/// Message of [`InitError::PageOverflow`].
+pub(crate) const PAGE_OVERFLOW: &str = "thread id is too large, its fallback page overflows";
+
+/// Message posted to the value of a thread, see [`ThreadLocal::post`].
+pub type Message<T> = Box<dyn FnOnce(&T) + Send>;
+
+/// Policy for evicting idle values, see [`ThreadLocal::with_eviction`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvictionPolicy {
+    /// Evict values that have not been accessed for the given duration.
+    #[cfg(feature = "std")]
+    Ttl(std::time::Duration),
+
+    /// Keep at most the given number of live values,
+    /// the least recently used ones are evicted first.
+    MaxLive(usize)
 }
 
 pub struct StackToken {
-    _marker: std::marker::PhantomData<*const ()>,
+    /// Context of current thread, set when token accesses its first value.
+    context: Cell<Option<NonNull<Context>>>,
+    _marker: PhantomData<*const ()>,
 }
 
 impl StackToken {
     #[doc(hidden)]
     pub unsafe fn __private_new() -> StackToken {
         StackToken {
-            _marker: std::marker::PhantomData,
+            context: Cell::new(None),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl Drop for StackToken {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(cx) = self.context.get() {
+            unsafe {
+                thread::leave(cx);
+            }
         }
     }
 }
@@ -86,37 +219,107 @@ macro_rules! stack_token {
 }
 
 impl<T: Send + 'static> ThreadLocal<T> {
+    /// Create a `ThreadLocal` with default number of lock-free threads.
+    ///
+    /// With `rayon` feature, when created on a worker of a rayon pool,
+    /// it is large enough for all workers of that pool.
     pub fn new() -> ThreadLocal<T> {
-        #[cfg(not(feature = "loom"))]
-        #[cfg(not(feature = "shuttle"))]
-        let default = 16;
+        ThreadLocal::with_threads(default_threads())
+    }
 
-        #[cfg(any(feature = "loom", feature = "shuttle"))]
-        let default = 3;
+    /// Create a `ThreadLocal` with `num` lock-free threads.
+    ///
+    /// Panics if `num` is zero, see [`Builder::try_build`] for fallible construction.
+    pub fn with_threads(num: usize) -> ThreadLocal<T> {
+        ThreadLocal::builder()
+            .threads(num)
+            .build()
+    }
 
-        ThreadLocal::with_threads(default)
+    /// Create a `ThreadLocal` that evicts idle values according to `policy`.
+    ///
+    /// Eviction never happens while the owner may still hold a reference.
+    /// The evicted value is dropped on its owner thread,
+    /// the next time that thread accesses a value with its outermost `StackToken`,
+    /// and is rebuilt by the next `get_or_init`.
+    ///
+    /// `MaxLive` is checked whenever a thread initializes its value,
+    /// `Ttl` is only checked by [`evict_idle`](ThreadLocal::evict_idle).
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use std::time::Duration;
+    /// use per_thread_object::{ ThreadLocal, EvictionPolicy };
+    ///
+    /// let tl: ThreadLocal<Vec<u8>> = ThreadLocal::with_eviction(16, EvictionPolicy::Ttl(Duration::ZERO));
+    ///
+    /// {
+    ///     per_thread_object::stack_token!(token);
+    ///     tl.get_or_init(token, || vec![0; 1024]);
+    /// }
+    ///
+    /// tl.evict_idle();
+    ///
+    /// per_thread_object::stack_token!(token);
+    /// assert!(tl.get(token).is_none());
+    /// ```
+    pub fn with_eviction(num: usize, policy: EvictionPolicy) -> ThreadLocal<T> {
+        ThreadLocal::builder()
+            .threads(num)
+            .eviction(policy)
+            .build()
     }
 
-    pub fn with_threads(num: usize) -> ThreadLocal<T> {
-        ThreadLocal {
-            pool: Storage::with_threads(num)
-        }
+    /// Create a `ThreadLocal` with custom configuration.
+    pub fn builder() -> Builder<T> {
+        Builder::new()
+    }
+}
+
+impl<T: Send + 'static, A: Allocator + Clone, Id: ThreadIdentity> ThreadLocal<T, A, Id> {
+    /// Free trailing fallback pages in which no thread holds a value.
+    ///
+    /// Pages are allocated for threads above the lock-free capacity
+    /// and otherwise kept until `ThreadLocal` is dropped.
+    pub fn shrink_to_fit(&mut self) {
+        self.pool.shrink_to_fit();
+    }
+
+    /// Request eviction of idle values according to the eviction policy.
+    ///
+    /// Does nothing if `ThreadLocal` has no eviction policy.
+    pub fn evict_idle(&self) {
+        self.pool.evict_idle();
     }
 
     #[inline]
-    pub fn get<'stack>(&'stack self, _token: &'stack StackToken) -> Option<&'stack T> {
-        unsafe {
-            self.pool.get(thread::get())
+    pub fn get<'stack>(&'stack self, token: &'stack StackToken) -> Option<&'stack T> {
+        let (id, session) = thread::enter::<Id>(token);
+        let slot = unsafe { self.pool.get(id)? };
+        let val = slot.value.with(|val| unsafe { (*val).as_ref() })?;
+
+        self.pool.touch(slot, session);
+
+        let flags = slot.flags.load(Ordering::Relaxed);
+        if flags != 0 {
+            self.or_visit(id, slot, val, flags);
         }
+
+        Some(val)
     }
 
+    /// Get the value of current thread, initialize it with `init` if not exists.
+    ///
+    /// Panics if `max_threads` threads already hold a value
+    /// and there is no overflow value, see [`Builder::max_threads`],
+    /// or if current thread cannot have a slot, see [`InitError::PageOverflow`].
     #[inline]
     pub fn get_or_init<'stack, F>(&'stack self, token: &'stack StackToken, init: F)
         -> &'stack T
     where
         F: FnOnce() -> T
     {
-        use std::convert::Infallible;
+        use core::convert::Infallible;
 
         match self.get_or_try_init::<_, Infallible>(token, || Ok(init())) {
             Ok(val) => val,
@@ -124,37 +327,328 @@ impl<T: Send + 'static> ThreadLocal<T> {
         }
     }
 
+    /// Get the value of current thread, initialize it with `init` if not exists.
+    ///
+    /// Panics in the same cases as `get_or_init`,
+    /// use [`try_get_or_init_capped`](ThreadLocal::try_get_or_init_capped) to get them as [`InitError`].
     #[inline]
-    pub fn get_or_try_init<'stack, F, E>(&'stack self, _token: &'stack StackToken, init: F)
+    pub fn get_or_try_init<'stack, F, E>(&'stack self, token: &'stack StackToken, init: F)
         -> Result<&'stack T, E>
     where
         F: FnOnce() -> Result<T, E>
     {
-        let id = thread::get();
-        let ptr = unsafe { self.pool.get_or_new(id) };
+        self.try_init(token, init).map_err(InitError::unwrap_init)
+    }
+
+    /// Get the value of current thread, initialize it with `init` if not exists.
+    ///
+    /// Returns [`InitError::Capacity`] if `max_threads` threads already hold a value
+    /// and there is no overflow value, see [`Builder::max_threads`],
+    /// or [`InitError::PageOverflow`] if current thread cannot have a slot.
+    #[inline]
+    pub fn try_get_or_init_capped<'stack, F, E>(&'stack self, token: &'stack StackToken, init: F)
+        -> Result<&'stack T, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        self.try_init(token, init)
+    }
+
+    #[inline]
+    pub(crate) fn try_init<'stack, F, E>(&'stack self, token: &'stack StackToken, init: F)
+        -> Result<&'stack T, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        let (id, session) = thread::enter::<Id>(token);
 
-        let obj = unsafe { &*ptr.as_ptr() };
-        let val = if let Some(val) = obj.with(|val| unsafe { &*val }) {
-            val
-        } else {
-            let newval = init()?;
-            let val = obj.with_mut(|val| unsafe { &mut *val }.get_or_insert(newval));
+        if let Some(slot) = unsafe { self.pool.get(id) } {
+            if let Some(val) = slot.value.with(|val| unsafe { (*val).as_ref() }) {
+                let flags = slot.flags.load(Ordering::Relaxed);
 
-            ThreadLocal::or_try(&self.pool, id, ptr);
+                if flags == 0 {
+                    self.pool.touch(slot, session);
+                    return Ok(val);
+                }
 
-            val
-        };
+                return self.or_flagged(id, slot, val, session, flags, init);
+            }
+        }
 
-        Ok(val)
+        self.or_init(id, session, init)
+    }
+
+    /// Get the value of current thread, build it with the initializer of builder if not exists.
+    ///
+    /// Panics if `ThreadLocal` was built without initializer, see [`Builder::init`].
+    #[inline]
+    pub fn get_or_create<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        self.get_or_init(token, || match &self.init {
+            Some(init) => init(),
+            None => panic!("`ThreadLocal` has no initializer")
+        })
+    }
+
+    /// Call `f` with the value of current thread.
+    ///
+    /// The `StackToken` is created inside, so it is safe to use in async code.
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(Option<&T>) -> R
+    {
+        stack_token!(token);
+        f(self.get(token))
+    }
+
+    /// Call `f` with the value of current thread, initialize it with `init` if not exists.
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use std::cell::Cell;
+    /// use per_thread_object::ThreadLocal;
+    ///
+    /// let tl: ThreadLocal<Cell<u32>> = ThreadLocal::new();
+    ///
+    /// tl.with_or_init(|| Cell::new(0), |val| val.set(val.get() + 1));
+    /// assert_eq!(Some(1), tl.with(|val| val.map(Cell::get)));
+    /// ```
+    pub fn with_or_init<I, F, R>(&self, init: I, f: F) -> R
+    where
+        I: FnOnce() -> T,
+        F: FnOnce(&T) -> R
+    {
+        stack_token!(token);
+        f(self.get_or_init(token, init))
+    }
+
+    /// Release the value of current thread.
+    ///
+    /// If no `StackToken` on current thread has accessed a value, value is dropped immediately.
+    /// Otherwise it is dropped when current thread accesses a value with its next outermost `StackToken`,
+    /// or at thread exit.
+    pub fn release_current(&self) {
+        if let Some(id) = thread::try_get::<Id>() {
+            self.pool.request_evict(id);
+            thread::try_evict::<Id>();
+        }
+    }
+
+    /// Report capacity, initialized slots and memory footprint.
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use per_thread_object::ThreadLocal;
+    ///
+    /// let tl: ThreadLocal<u32> = ThreadLocal::with_threads(4);
+    ///
+    /// per_thread_object::stack_token!(token);
+    /// tl.get_or_init(token, || 1);
+    ///
+    /// let stats = tl.stats();
+    /// assert_eq!(4, stats.capacity);
+    /// assert_eq!(1, stats.threads);
+    /// ```
+    pub fn stats(&self) -> Stats {
+        self.pool.stats()
+    }
+
+    /// Force all threads to rebuild their value.
+    ///
+    /// Value is rebuilt by the next `get_or_init` of its owner,
+    /// unless a `StackToken` that is still alive has observed the old value,
+    /// then it is rebuilt once all of them have been dropped.
+    /// The old value is dropped on the owner thread.
+    ///
+    /// `get` still returns the old value until it is rebuilt.
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use per_thread_object::ThreadLocal;
+    ///
+    /// let tl: ThreadLocal<u32> = ThreadLocal::new();
+    ///
+    /// {
+    ///     per_thread_object::stack_token!(token);
+    ///     let old = tl.get_or_init(token, || 1);
+    ///
+    ///     tl.invalidate_all();
+    ///
+    ///     // `old` is still alive
+    ///     assert_eq!(1, *tl.get_or_init(token, || 2));
+    ///     assert_eq!(1, *old);
+    /// }
+    ///
+    /// {
+    ///     per_thread_object::stack_token!(token);
+    ///     assert_eq!(2, *tl.get_or_init(token, || 2));
+    /// }
+    /// ```
+    pub fn invalidate_all(&self) {
+        self.pool.invalidate();
+    }
+
+    /// Post a message to the value of `thread`.
+    ///
+    /// The owner thread processes pending messages on its next `get`,
+    /// or by calling [`drain_mailbox`](ThreadLocal::drain_mailbox).
+    ///
+    /// Returns message if `thread` has no value or has exited.
+    /// Messages that are still pending when the owner exits or its value is evicted
+    /// are kept for [`take_undelivered`](ThreadLocal::take_undelivered).
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use std::cell::Cell;
+    /// use per_thread_object::{ ThreadLocal, ThreadId };
+    ///
+    /// let tl: ThreadLocal<Cell<u32>> = ThreadLocal::new();
+    ///
+    /// per_thread_object::stack_token!(token);
+    ///
+    /// tl.get_or_init(token, || Cell::new(1));
+    /// assert!(tl.post(ThreadId::current(), |val| val.set(2)).is_ok());
+    /// assert_eq!(2, tl.get(token).unwrap().get());
+    /// ```
+    pub fn post<F>(&self, thread: ThreadId, msg: F) -> Result<(), Message<T>>
+    where
+        F: FnOnce(&T) + Send + 'static
+    {
+        self.pool.post(thread.index(), thread.serial(), Box::new(msg))
+    }
+
+    /// Process pending messages of current thread.
+    pub fn drain_mailbox(&self, token: &StackToken) {
+        let _ = self.get(token);
+    }
+
+    /// Take messages whose target thread exited or lost its value before processing them.
+    pub fn take_undelivered(&self) -> Vec<Message<T>> {
+        self.pool.take_undelivered()
+    }
+
+    /// Visit the values of all threads.
+    ///
+    /// No thread can drop its value while `f` is running.
+    pub(crate) fn with_iter<R, F>(&self, f: F) -> R
+    where
+        T: Sync,
+        F: for<'a> FnOnce(Iter<'a, T>) -> R
+    {
+        unsafe {
+            self.pool.with_iter(f)
+        }
     }
 
     #[cold]
-    fn or_try(pool: &Storage<T>, id: usize, ptr: NonNull<UnsafeCell<Option<T>>>) {
+    fn or_init<F, E>(&self, id: usize, session: u64, init: F) -> Result<&T, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        // check before allocating a fallback page for this thread.
+        if !self.pool.has_room(id) {
+            return self.or_overflow();
+        }
+
+        let ptr = match unsafe { self.pool.get_or_new(id) } {
+            Some(ptr) => ptr,
+            None => return Err(InitError::PageOverflow)
+        };
+        let slot = unsafe { &*ptr.as_ptr() };
+        let generation = self.pool.generation();
+
+        let newval = init().map_err(InitError::Init)?;
+        self.or_try(id, slot, session, generation, newval)
+    }
+
+    fn or_try<'stack, E>(
+        &'stack self,
+        id: usize,
+        slot: &'stack Slot<T>,
+        session: u64,
+        generation: usize,
+        newval: T
+    ) -> Result<&'stack T, InitError<E>> {
         let thread_handle = unsafe {
-            thread::push(pool.as_threads_ref(), ptr)
+            thread::push::<Id, _>(self.pool.as_threads_ref(), NonNull::from(slot), self.pool.mail())
         };
 
-        pool.insert_thread_handle(id, thread_handle);
+        match unsafe { self.pool.insert(id, slot, thread_handle, generation, newval) } {
+            Ok(val) => {
+                slot.set_session(session);
+                Ok(val)
+            },
+            Err(newval) => {
+                // other threads took the remaining room after the check.
+                drop(newval);
+                self.or_overflow()
+            }
+        }
+    }
+
+    #[cold]
+    fn or_overflow<E>(&self) -> Result<&T, InitError<E>> {
+        self.overflow.as_ref().ok_or(InitError::Capacity)
+    }
+
+    #[cold]
+    fn or_flagged<'stack, F, E>(
+        &'stack self,
+        id: usize,
+        slot: &'stack Slot<T>,
+        val: &'stack T,
+        session: u64,
+        flags: u32,
+        init: F
+    ) -> Result<&'stack T, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        // references handed out in previous sessions are all dead,
+        // otherwise we have to wait for next session.
+        if flags & page::STALE != 0 && !slot.in_session(session) {
+            let generation = self.pool.generation();
+            let old = unsafe { self.pool.take(slot) };
+            drop(old);
+
+            let newval = init().map_err(InitError::Init)?;
+            return self.or_try(id, slot, session, generation, newval);
+        }
+
+        self.pool.touch(slot, session);
+        self.or_visit(id, slot, val, flags);
+
+        Ok(val)
+    }
+
+    #[cold]
+    fn or_visit(&self, id: usize, slot: &Slot<T>, val: &T, flags: u32) {
+        if flags & page::STAMP != 0 {
+            self.pool.stamp(id);
+        }
+
+        if flags & page::MAIL != 0 {
+            // clear before taking, so later posts set it again.
+            slot.flags.fetch_and(!page::MAIL, Ordering::Acquire);
+
+            for msg in self.pool.take_mail(id) {
+                msg(val);
+            }
+        }
+    }
+}
+
+impl<T, A, Id> IntoIterator for ThreadLocal<T, A, Id>
+where
+    T: Send + 'static,
+    A: Allocator + Clone,
+    Id: ThreadIdentity
+{
+    type Item = T;
+    type IntoIter = alloc::vec::IntoIter<T>;
+
+    /// Take the values of all threads.
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.pool.drain().into_iter()
     }
 }
 
@@ -165,5 +659,63 @@ impl<T: Send + 'static> Default for ThreadLocal<T> {
     }
 }
 
-unsafe impl<T: Send> Send for ThreadLocal<T> {}
-unsafe impl<T: Send> Sync for ThreadLocal<T> {}
+impl<E> InitError<E> {
+    /// Take the error of initializer, panic on the others.
+    #[cold]
+    pub(crate) fn unwrap_init(self) -> E {
+        match self {
+            InitError::Init(err) => err,
+            InitError::Capacity => panic!("{}", CapacityExceeded),
+            InitError::PageOverflow => panic!("{}", PAGE_OVERFLOW)
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for InitError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Init(err) => err.fmt(f),
+            InitError::Capacity => CapacityExceeded.fmt(f),
+            InitError::PageOverflow => f.write_str(PAGE_OVERFLOW)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for InitError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InitError::Init(err) => Some(err),
+            InitError::Capacity | InitError::PageOverflow => None
+        }
+    }
+}
+
+unsafe impl<T: Send, A: Allocator + Clone + Send, Id: ThreadIdentity> Send for ThreadLocal<T, A, Id> {}
+unsafe impl<T: Send, A: Allocator + Clone + Send + Sync, Id: ThreadIdentity> Sync for ThreadLocal<T, A, Id> {}
+
+/// Report allocation of thread ids, which are shared by all `ThreadLocal`.
+///
+/// `max` is the number of slots needed for all threads to be lock-free.
+pub fn thread_stats() -> ThreadStats {
+    thread::stats()
+}
+
+fn default_threads() -> usize {
+    #[cfg(not(feature = "loom"))]
+    #[cfg(not(feature = "shuttle"))]
+    let default = 16;
+
+    #[cfg(any(feature = "loom", feature = "shuttle"))]
+    let default = 3;
+
+    // workers of rayon pool and the thread that spawns them,
+    // only on a worker, so that creating a storage never starts the global pool.
+    #[cfg(feature = "rayon")]
+    let default = match rayon::current_thread_index() {
+        Some(_) => default.max(rayon::current_num_threads() + 1),
+        None => default
+    };
+
+    default
+}
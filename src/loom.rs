@@ -1,16 +1,37 @@
+#[cfg(feature = "std")]
 #[cfg(not(feature = "shuttle"))]
+#[cfg(not(feature = "fork"))]
 pub use std::sync;
 
+#[cfg(feature = "fork")]
+#[cfg(not(feature = "shuttle"))]
+pub mod sync {
+    pub use std::sync::{ Arc, atomic };
+    pub use super::fork::Mutex;
+}
+
+#[cfg(not(feature = "std"))]
+pub mod sync {
+    pub use alloc::sync::Arc;
+    pub use core::sync::atomic;
+    pub use super::spin::Mutex;
+}
+
 #[cfg(feature = "shuttle")]
-pub use shuttle::sync;
+pub use shuttle::{ sync, thread };
 
 pub mod cell {
-    pub struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+    pub struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
 
     impl<T> UnsafeCell<T> {
         #[inline]
         pub fn new(t: T) -> UnsafeCell<T> {
-            UnsafeCell(std::cell::UnsafeCell::new(t))
+            UnsafeCell(core::cell::UnsafeCell::new(t))
+        }
+
+        #[inline]
+        pub fn into_inner(self) -> T {
+            self.0.into_inner()
         }
 
         #[inline]
@@ -28,3 +49,218 @@ pub mod cell {
         }
     }
 }
+
+/// Spin lock used in place of `std::sync::Mutex` without `std` feature.
+///
+/// `lock` returns `Result` like std, so call sites are shared,
+/// but it never fails because there is no poisoning.
+#[cfg(not(feature = "std"))]
+pub mod spin {
+    use core::ops;
+    use core::cell::UnsafeCell;
+    use core::convert::Infallible;
+    use core::sync::atomic::{ AtomicBool, Ordering };
+
+    pub struct Mutex<T> {
+        locked: AtomicBool,
+        value: UnsafeCell<T>
+    }
+
+    pub struct MutexGuard<'a, T> {
+        lock: &'a Mutex<T>
+    }
+
+    impl<T> Mutex<T> {
+        #[inline]
+        pub const fn new(value: T) -> Mutex<T> {
+            Mutex {
+                locked: AtomicBool::new(false),
+                value: UnsafeCell::new(value)
+            }
+        }
+
+        pub fn lock(&self) -> Result<MutexGuard<'_, T>, Infallible> {
+            while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+                while self.locked.load(Ordering::Relaxed) {
+                    core::hint::spin_loop();
+                }
+            }
+
+            Ok(MutexGuard { lock: self })
+        }
+    }
+
+    impl<T> ops::Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        #[inline]
+        fn deref(&self) -> &T {
+            unsafe { &*self.lock.value.get() }
+        }
+    }
+
+    impl<T> ops::DerefMut for MutexGuard<'_, T> {
+        #[inline]
+        fn deref_mut(&mut self) -> &mut T {
+            unsafe { &mut *self.lock.value.get() }
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        #[inline]
+        fn drop(&mut self) {
+            self.lock.locked.store(false, Ordering::Release);
+        }
+    }
+
+    unsafe impl<T: Send> Send for Mutex<T> {}
+    unsafe impl<T: Send> Sync for Mutex<T> {}
+    unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+}
+
+/// `std::sync::Mutex` that `fork` waits for.
+///
+/// The prepare handler of `fork` blocks new lock holders
+/// and waits until no thread holds a lock of this crate,
+/// so the child never inherits a lock held by a thread that does not exist.
+///
+/// Holders are counted in stripes picked by thread,
+/// so threads locking unrelated storages rarely share a counter.
+#[cfg(feature = "fork")]
+#[cfg(not(feature = "shuttle"))]
+pub mod fork {
+    use std::{ ops, sync, thread };
+    use std::cell::Cell;
+    use std::mem::ManuallyDrop;
+    use std::sync::{ LockResult, PoisonError };
+    use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+    use crossbeam_utils::CachePadded;
+
+    const STRIPES: usize = 64;
+
+    static FORKING: AtomicBool = AtomicBool::new(false);
+
+    /// Number of threads holding at least one lock, in the stripe of each thread.
+    static HOLDERS: [CachePadded<AtomicUsize>; STRIPES] = {
+        // only used to repeat in the initializer.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: CachePadded<AtomicUsize> = CachePadded::new(AtomicUsize::new(0));
+        [ZERO; STRIPES]
+    };
+
+    thread_local!{
+        /// Number of locks held by current thread.
+        static HELD: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub struct Mutex<T>(sync::Mutex<T>);
+
+    pub struct MutexGuard<'a, T>(ManuallyDrop<sync::MutexGuard<'a, T>>);
+
+    impl<T> Mutex<T> {
+        #[inline]
+        pub const fn new(value: T) -> Mutex<T> {
+            Mutex(sync::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+            enter();
+
+            match self.0.lock() {
+                Ok(guard) => Ok(MutexGuard(ManuallyDrop::new(guard))),
+                Err(err) => Err(PoisonError::new(MutexGuard(ManuallyDrop::new(err.into_inner()))))
+            }
+        }
+    }
+
+    impl<T> ops::Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        #[inline]
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    impl<T> ops::DerefMut for MutexGuard<'_, T> {
+        #[inline]
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.0
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        #[inline]
+        fn drop(&mut self) {
+            // unlock before leaving, so fork never happens while it is still locked.
+            unsafe {
+                ManuallyDrop::drop(&mut self.0);
+            }
+
+            leave();
+        }
+    }
+
+    /// Stripe of current thread, from the address of its thread local,
+    /// which is stable for the lifetime of the thread.
+    #[inline]
+    fn stripe(held: &Cell<usize>) -> &'static AtomicUsize {
+        let addr = held as *const Cell<usize> as u64;
+        let index = addr.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - STRIPES.trailing_zeros());
+        &HOLDERS[index as usize]
+    }
+
+    fn enter() {
+        HELD.with(|held| {
+            if held.get() == 0 {
+                let holders = stripe(held);
+
+                loop {
+                    while FORKING.load(Ordering::Acquire) {
+                        thread::yield_now();
+                    }
+
+                    holders.fetch_add(1, Ordering::SeqCst);
+
+                    if !FORKING.load(Ordering::SeqCst) {
+                        break
+                    }
+
+                    holders.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+
+            held.set(held.get() + 1);
+        });
+    }
+
+    fn leave() {
+        HELD.with(|held| {
+            held.set(held.get() - 1);
+
+            if held.get() == 0 {
+                stripe(held).fetch_sub(1, Ordering::Release);
+            }
+        });
+    }
+
+    /// Called before `fork`, waits until other threads release their locks.
+    pub fn prepare() {
+        while FORKING.swap(true, Ordering::SeqCst) {
+            thread::yield_now();
+        }
+
+        let own = HELD.with(|held| usize::from(held.get() != 0));
+
+        // a stripe never counts less than its holders that saw `FORKING` unset,
+        // and the others only add themselves for a moment.
+        while HOLDERS.iter().map(|holders| holders.load(Ordering::SeqCst)).sum::<usize>() != own {
+            thread::yield_now();
+        }
+    }
+
+    /// Called after `fork` in both parent and child.
+    pub fn release() {
+        FORKING.store(false, Ordering::Release);
+    }
+}
@@ -1,128 +1,893 @@
-use std::mem;
-use std::ptr::NonNull;
-use std::mem::ManuallyDrop;
-use std::collections::BTreeMap;
-use crossbeam_utils::CachePadded;
-use crate::thread::ThreadHandle;
+use core::mem;
+use core::cell::Cell;
+use core::cmp::Ordering as CmpOrdering;
+use core::ptr::NonNull;
+use core::mem::ManuallyDrop;
+use core::convert::TryFrom;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::alloc::handle_alloc_error;
+use alloc::collections::{ btree_map, BTreeMap };
+use crate::thread::{ ThreadHandle, IdPool };
 use crate::loom::cell::UnsafeCell;
 use crate::loom::sync::Mutex;
-use crate::util::BoxTail;
+use crate::loom::sync::atomic::{ AtomicUsize, AtomicU32, AtomicU64, Ordering };
+use crate::util::{ BoxTail, Array, AllocError };
+use crate::allocator::{ Allocator, Global };
+use crate::builder::Config;
+use crate::{ Message, EvictionPolicy, Padding, BuildError };
 
 
-pub struct Storage<T> {
-    inner: BoxTail<Inner<T>, FastPageElem<T>>
+pub struct Storage<T, A: Allocator + Clone = Global> {
+    inner: BoxTail<Inner<T, A>, ManuallyDrop<Slot<T>>, A>
 }
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Clone, Copy)]
 pub struct ThreadsRef {
-    ptr: NonNull<Mutex<BTreeMap<usize, ThreadHandle>>>
+    ptr: NonNull<Mutex<BTreeMap<usize, ThreadHandle>>>,
+
+    /// Creation order of storage, so thread lists are visited in the same order every run.
+    serial: u64,
+
+    /// Set when storage frees trailing pages at thread exit.
+    trim: Option<Trim>,
+
+    #[cfg(feature = "stats")]
+    counters: NonNull<Counters>
+}
+
+#[derive(Clone, Copy)]
+struct Trim {
+    inner: NonNull<()>,
+    capacity: usize,
+    trim: unsafe fn(&Trim, &BTreeMap<usize, ThreadHandle>, &IdPool, usize) -> Option<Freed>
+}
+
+/// Pages split off from a storage, freed when it is dropped.
+///
+/// It is returned so that pages are freed after storage locks are released,
+/// because the allocator may be slow.
+struct Freed {
+    ptr: NonNull<()>,
+    free: unsafe fn(NonNull<()>)
+}
+
+/// Value was built before `invalidate_all`.
+pub const STALE: u32 = 1;
+
+/// Owner has pending messages.
+pub const MAIL: u32 = 1 << 1;
+
+/// Access must be stamped for eviction.
+pub const STAMP: u32 = 1 << 2;
+
+/// State of each thread, other features keep their state out of it,
+/// see `Mail` and `Inner::stamps`.
+pub struct Slot<T> {
+    pub value: UnsafeCell<Option<T>>,
+
+    /// Work that owner must do before handing out value, see `STALE`, `MAIL` and `STAMP`.
+    ///
+    /// It is zero in most case, so owner checks everything with one load.
+    pub flags: AtomicU32,
+
+    /// Last session in which value was handed out, only accessed by owner.
+    ///
+    /// Session is truncated, a session that wraps around only delays rebuild.
+    session: Cell<u32>,
+
+    /// Number of accesses by owner, only written by owner.
+    #[cfg(feature = "stats")]
+    hits: AtomicU64
+}
+
+/// Messages of a storage, only allocated once a message is posted.
+pub struct Mail<T> {
+    /// Messages posted to registered threads, by thread id.
+    ///
+    /// It is only pushed while the owner is registered,
+    /// and taken by the owner, or when the owner is unregistered.
+    pending: BTreeMap<usize, Vec<Message<T>>>,
+
+    /// Messages whose target thread dropped its value before processing them.
+    undelivered: Vec<Message<T>>
 }
 
-struct Inner<T> {
+struct Inner<T, A: Allocator> {
+    serial: u64,
+    generation: AtomicUsize,
+    eviction: Option<EvictionPolicy>,
+    max_threads: Option<usize>,
+    shrink_on_exit: bool,
+    padding: Padding,
+    page_size: usize,
+
+    /// Number of fallback pages whose size does not overflow.
+    max_pages: usize,
+
+    /// Clock of access stamps.
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+
+    /// Without a clock, stamps only order accesses.
+    #[cfg(not(feature = "std"))]
+    ticks: AtomicU64,
+
     threads: Mutex<BTreeMap<usize, ThreadHandle>>,
-    fallback: Mutex<Vec<Page<T>>>,
+    fallback: Mutex<Vec<Page<T, A>>>,
+    mail: Mutex<Mail<T>>,
+
+    /// Nanoseconds since storage creation of last access by owner of each lock-free slot,
+    /// only allocated with eviction policy.
+    stamps: Box<[AtomicU64]>,
+
+    /// Allocator of fallback pages.
+    alloc: A,
+
+    #[cfg(feature = "stats")]
+    counters: Counters
+}
+
+#[cfg(feature = "stats")]
+struct Counters {
+    inits: AtomicU64,
+    exit_drops: AtomicU64,
+    releases: AtomicU64
 }
 
-type FastPageElem<T> = CachePadded<ManuallyDrop<UnsafeCell<Option<T>>>>;
+struct Page<T, A: Allocator> {
+    ptr: BoxTail<(), ManuallyDrop<Slot<T>>, A>,
 
-struct Page<T> {
-    ptr: Box<[ManuallyDrop<UnsafeCell<Option<T>>>]>
+    /// Same as `Inner::stamps`.
+    stamps: Box<[AtomicU64]>
+}
+
+pub struct Iter<'a, T> {
+    ids: btree_map::Keys<'a, usize, ThreadHandle>,
+    array: Array<'a, ManuallyDrop<Slot<T>>>,
+    pages: Vec<Array<'a, ManuallyDrop<Slot<T>>>>,
+    page_size: usize,
+}
+
+/// Statistics of a `ThreadLocal`, see [`ThreadLocal::stats`](crate::ThreadLocal::stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Number of lock-free slots.
+    pub capacity: usize,
+
+    /// Number of initialized slots in the lock-free array.
+    pub fast_slots: usize,
+
+    /// Number of initialized slots in fallback pages.
+    pub fallback_slots: usize,
+
+    /// Number of allocated fallback pages.
+    pub pages: usize,
+
+    /// Bytes reserved for slots, including the array and all pages.
+    pub reserved_bytes: usize,
+
+    /// Number of threads that registered a value.
+    pub threads: usize,
+
+    /// Number of accesses to slots in the lock-free array.
+    #[cfg(feature = "stats")]
+    pub fast_hits: u64,
+
+    /// Number of accesses to slots in fallback pages.
+    #[cfg(feature = "stats")]
+    pub fallback_hits: u64,
+
+    /// Number of values initialized.
+    #[cfg(feature = "stats")]
+    pub inits: u64,
+
+    /// Number of values dropped at thread exit.
+    #[cfg(feature = "stats")]
+    pub exit_drops: u64,
+
+    /// Number of values released by dropping or draining storage.
+    #[cfg(feature = "stats")]
+    pub releases: u64
 }
 
 impl<T> Storage<T> {
     pub fn with_threads(num: usize) -> Storage<T> {
-        let inner = BoxTail::new(
+        Storage::new(&Config::new(num))
+    }
+
+    pub fn new(config: &Config) -> Storage<T> {
+        match Storage::try_new_in(config, Global) {
+            Ok(storage) => storage,
+            Err(err) => panic!("failed to create thread-local storage: {}", err)
+        }
+    }
+}
+
+impl<T, A: Allocator + Clone> Storage<T, A> {
+    pub fn try_new_in(config: &Config, alloc: A) -> Result<Storage<T, A>, BuildError> {
+        let max_pages = config.validate::<ManuallyDrop<Slot<T>>>()?;
+
+        let inner = BoxTail::try_new_in(
             Inner {
+                serial: next_serial(),
+                generation: AtomicUsize::new(0),
+                eviction: config.eviction,
+                max_threads: config.max_threads,
+                shrink_on_exit: config.shrink_on_exit,
+                padding: config.padding,
+                page_size: config.page_size.unwrap_or(config.capacity),
+                max_pages,
+                #[cfg(feature = "std")]
+                start: std::time::Instant::now(),
+                #[cfg(not(feature = "std"))]
+                ticks: AtomicU64::new(0),
                 threads: Mutex::new(BTreeMap::new()),
                 fallback: Mutex::new(Vec::new()),
+                mail: Mutex::new(Mail {
+                    pending: BTreeMap::new(),
+                    undelivered: Vec::new()
+                }),
+                stamps: new_stamps(config.eviction.is_some(), config.capacity),
+                alloc: alloc.clone(),
+
+                #[cfg(feature = "stats")]
+                counters: Counters {
+                    inits: AtomicU64::new(0),
+                    exit_drops: AtomicU64::new(0),
+                    releases: AtomicU64::new(0)
+                }
             },
-            num,
-            |ptr: *mut FastPageElem<T>| unsafe {
-                ptr.write(CachePadded::new(ManuallyDrop::new(UnsafeCell::new(None))));
-            }
+            config.capacity,
+            config.padding.align(),
+            |ptr: *mut ManuallyDrop<Slot<T>>| unsafe {
+                ptr.write(ManuallyDrop::new(Slot::new()));
+            },
+            alloc
         );
 
-        Storage { inner }
+        match inner {
+            Ok(inner) => Ok(Storage { inner }),
+            Err(AllocError::CapacityOverflow) => Err(BuildError::CapacityOverflow),
+            Err(AllocError::Failed(_)) => Err(BuildError::AllocFailed)
+        }
     }
 
     #[inline]
     pub fn as_threads_ref(&self) -> ThreadsRef {
+        let trim = if self.inner.shrink_on_exit {
+            Some(Trim {
+                inner: NonNull::from(&*self.inner).cast(),
+                capacity: self.inner.array_len(),
+                trim: Storage::<T, A>::trim_at_exit
+            })
+        } else {
+            None
+        };
+
         ThreadsRef {
-            ptr: NonNull::from(&self.inner.threads)
+            ptr: NonNull::from(&self.inner.threads),
+            serial: self.inner.serial,
+            trim,
+
+            #[cfg(feature = "stats")]
+            counters: NonNull::from(&self.inner.counters)
+        }
+    }
+
+    pub fn generation(&self) -> usize {
+        self.inner.generation.load(Ordering::Relaxed)
+    }
+
+    /// Mark values of all threads as stale.
+    ///
+    /// Values being built now are marked by `insert`, because generation has changed.
+    pub fn invalidate(&self) {
+        let threads = self.inner.threads.lock().unwrap();
+        self.inner.generation.fetch_add(1, Ordering::Relaxed);
+
+        for &id in threads.keys() {
+            // # Safety
+            //
+            // registered thread always has its slot allocated.
+            let slot = unsafe { self.get(id).unwrap() };
+            slot.flags.fetch_or(STALE, Ordering::Relaxed);
+        }
+    }
+
+    /// Record access of owner thread.
+    #[inline]
+    pub fn touch(&self, slot: &Slot<T>, session: u64) {
+        #[cfg(feature = "stats")]
+        slot.hits.store(slot.hits.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+
+        slot.set_session(session);
+    }
+
+    /// Stamp access of thread `id`, if storage has eviction policy.
+    pub fn stamp(&self, id: usize) {
+        if self.inner.eviction.is_some() {
+            let now = self.inner.now();
+            self.with_stamp(id, |stamp| stamp.store(now, Ordering::Relaxed));
+        }
+    }
+
+    /// Call `f` with access stamp of thread `id`, `None` if it has no slot.
+    fn with_stamp<R, F: FnOnce(&AtomicU64) -> R>(&self, id: usize, f: F) -> Option<R> {
+        let inner = &self.inner;
+        let (page_id, index) = map_index(inner.array_len(), inner.page_size, id);
+
+        if page_id == 0 {
+            inner.stamps.get(index).map(f)
+        } else {
+            inner.fallback.lock()
+                .unwrap()
+                .get(page_id - 1)?
+                .stamps
+                .get(index)
+                .map(f)
+        }
+    }
+
+    /// Ask the owners of idle values to evict them.
+    ///
+    /// Values are dropped by their owner thread
+    /// when it accesses a value with its next outermost `StackToken`.
+    pub fn evict_idle(&self) {
+        let policy = match self.inner.eviction {
+            Some(policy) => policy,
+            None => return
+        };
+        let victims = {
+            let threads = self.inner.threads.lock().unwrap();
+            let mut stamps = threads.iter()
+                .map(|(&id, handle)| {
+                    // registered thread always has its slot allocated.
+                    let last = self.with_stamp(id, |stamp| stamp.load(Ordering::Relaxed));
+                    (last.unwrap_or(0), handle)
+                })
+                .collect::<Vec<_>>();
+
+            match policy {
+                #[cfg(feature = "std")]
+                EvictionPolicy::Ttl(ttl) => {
+                    let now = self.inner.now();
+                    let ttl = u64::try_from(ttl.as_nanos()).unwrap_or(u64::MAX);
+                    stamps.retain(|&(last, _)| now.saturating_sub(last) >= ttl);
+                },
+                EvictionPolicy::MaxLive(max) => {
+                    let excess = stamps.len().saturating_sub(max);
+                    stamps.sort_by_key(|&(last, _)| last);
+                    stamps.truncate(excess);
+                }
+            }
+
+            stamps.into_iter()
+                .map(|(_, handle)| handle.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // request without holding the thread list,
+        // because owner locks its own list before the thread list.
+        let tr = self.as_threads_ref();
+        for handle in victims {
+            handle.request_evict(&tr);
         }
     }
 
-    pub fn insert_thread_handle(&self, id: usize, handle: ThreadHandle) {
-        self.inner.threads.lock()
+    /// Ask thread to evict its value, regardless of eviction policy.
+    pub fn request_evict(&self, id: usize) {
+        let handle = self.inner.threads.lock()
             .unwrap()
-            .insert(id, handle);
+            .get(&id)
+            .cloned();
+
+        if let Some(handle) = handle {
+            handle.request_evict(&self.as_threads_ref());
+        }
+    }
+
+    /// Whether thread `id` can register a value without exceeding `max_threads`.
+    pub fn has_room(&self, id: usize) -> bool {
+        match self.inner.max_threads {
+            Some(max) => {
+                let threads = self.inner.threads.lock().unwrap();
+                threads.len() < max || threads.contains_key(&id)
+            },
+            None => true
+        }
+    }
+
+    /// Store value of current thread and register it.
+    ///
+    /// If value already exists, `value` is dropped and existing one is returned.
+    /// If `max_threads` threads already hold a value, `value` is returned back.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must belong to current thread.
+    pub unsafe fn insert(&self, id: usize, slot: &Slot<T>, handle: ThreadHandle, generation: usize, value: T)
+        -> Result<&T, T>
+    {
+        let mut threads = self.inner.threads.lock().unwrap();
+
+        if let Some(max) = self.inner.max_threads {
+            if threads.len() >= max && !threads.contains_key(&id) {
+                drop(threads);
+
+                // unregister without thread list locked,
+                // because owner locks its own list before the thread list.
+                handle.unregister(&self.as_threads_ref());
+
+                return Err(value);
+            }
+        }
+
+        threads.insert(id, handle);
+
+        let over_limit = match self.inner.eviction {
+            Some(EvictionPolicy::MaxLive(max)) => threads.len() > max,
+            _ => false
+        };
+
+        // write while the thread list is locked,
+        // because the slot may be visible to iterator.
+        let (val, value) = slot.value.with_mut(|val| {
+            let val = &mut *val;
+
+            if val.is_none() {
+                // posts and invalidation also hold the thread list,
+                // so no flag can be set concurrently.
+                let mut flags = 0;
+                if generation != self.inner.generation.load(Ordering::Relaxed) {
+                    flags |= STALE;
+                }
+                if self.inner.mail.lock().unwrap().pending.contains_key(&id) {
+                    flags |= MAIL;
+                }
+                if self.inner.eviction.is_some() {
+                    flags |= STAMP;
+                }
+                slot.flags.store(flags, Ordering::Relaxed);
+
+                #[cfg(feature = "stats")]
+                self.inner.counters.inits.fetch_add(1, Ordering::Relaxed);
+
+                (val.get_or_insert(value), None)
+            } else {
+                (val.as_mut().unwrap(), Some(value))
+            }
+        });
+
+        drop(threads);
+        drop(value);
+
+        self.stamp(id);
+
+        if over_limit {
+            self.evict_idle();
+        }
+
+        Ok(val)
+    }
+
+    /// Take value of current thread, but keep it registered.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must belong to current thread, and no reference to its value is alive.
+    pub unsafe fn take(&self, slot: &Slot<T>) -> Option<T> {
+        let _threads = self.inner.threads.lock().unwrap();
+        slot.value.with_mut(|val| (*val).take())
     }
 
     #[inline]
-    pub unsafe fn get(&self, id: usize) -> Option<&T> {
+    pub unsafe fn get(&self, id: usize) -> Option<&Slot<T>> {
         let inner = &self.inner;
-        let (page_id, index) = map_index(inner.array_len(), id);
+        let (page_id, index) = map_index(inner.array_len(), inner.page_size, id);
 
         if page_id == 0 {
-            inner.array().get_unchecked(index)
-                .with(|obj| (*obj).as_ref())
+            Some(inner.array().get_unchecked(index))
         } else {
             Storage::or_get(inner, page_id, index)
         }
     }
 
+    /// Get slot of thread `id`, allocate its page if needed.
+    ///
+    /// Returns `None` if the page is too large to allocate.
     #[inline]
-    pub unsafe fn get_or_new(&self, id: usize) -> NonNull<UnsafeCell<Option<T>>> {
+    pub unsafe fn get_or_new(&self, id: usize) -> Option<NonNull<Slot<T>>> {
         let inner = &self.inner;
-        let (page_id, index) = map_index(inner.array_len(), id);
+        let (page_id, index) = map_index(inner.array_len(), inner.page_size, id);
 
         if page_id == 0 {
             let ptr = inner.array().get_unchecked(index);
-            let ptr = &***ptr as *const UnsafeCell<Option<_>>;
-            NonNull::new_unchecked(ptr as *mut _)
+            let ptr = &**ptr as *const Slot<_>;
+            Some(NonNull::new_unchecked(ptr as *mut _))
         } else {
-            Storage::or_new(inner, inner.array_len(), page_id, index)
+            Storage::or_new(inner, page_id, index)
         }
     }
 
+    /// Visit the values of all registered threads.
+    ///
+    /// The thread list stays locked while `f` runs,
+    /// so an exiting thread cannot drop its value underneath the iterator.
+    pub unsafe fn with_iter<R, F>(&self, f: F) -> R
+    where
+        F: for<'a> FnOnce(Iter<'a, T>) -> R
+    {
+        let inner = &self.inner;
+        let threads = inner.threads.lock().unwrap();
+
+        f(Iter {
+            ids: threads.keys(),
+            array: inner.array(),
+            pages: Storage::pages(inner),
+            page_size: inner.page_size
+        })
+    }
+
+    /// Post message to thread.
+    ///
+    /// Returns message if thread has no value.
+    pub fn post(&self, id: usize, serial: u64, msg: Message<T>) -> Result<(), Message<T>> {
+        let inner = &self.inner;
+        let threads = inner.threads.lock().unwrap();
+
+        match threads.get(&id) {
+            Some(handle) if handle.serial() == serial => (),
+            _ => return Err(msg)
+        }
+
+        // # Safety
+        //
+        // registered thread always has its slot allocated.
+        let slot = unsafe { self.get(id).unwrap() };
+
+        // push while the thread list is locked,
+        // so owner cannot exit between check and push.
+        inner.mail.lock()
+            .unwrap()
+            .pending
+            .entry(id)
+            .or_default()
+            .push(msg);
+        slot.flags.fetch_or(MAIL, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Take messages posted to thread `id`.
+    pub fn take_mail(&self, id: usize) -> Vec<Message<T>> {
+        self.inner.mail.lock()
+            .unwrap()
+            .pending
+            .remove(&id)
+            .unwrap_or_default()
+    }
+
+    /// Messages of storage, pending messages of a thread are moved to undelivered
+    /// whenever its value is removed from thread list.
+    pub fn mail(&self) -> NonNull<Mutex<Mail<T>>> {
+        NonNull::from(&self.inner.mail)
+    }
+
+    /// Take messages whose target thread dropped its value before processing them.
+    pub fn take_undelivered(&self) -> Vec<Message<T>> {
+        mem::take(&mut self.inner.mail.lock().unwrap().undelivered)
+    }
+
+    pub fn stats(&self) -> Stats {
+        let inner = &self.inner;
+        let threads = inner.threads.lock().unwrap();
+        let pages = Storage::pages(inner);
+        let capacity = inner.array_len();
+
+        let mut stats = Stats {
+            capacity,
+            pages: pages.len(),
+            reserved_bytes: inner.alloc_size() + mem::size_of_val(&*inner.stamps) + Storage::pages_size(inner),
+            threads: threads.len(),
+            ..Stats::default()
+        };
+
+        for &id in threads.keys() {
+            let (page_id, index) = map_index(capacity, inner.page_size, id);
+
+            let slot = if page_id == 0 {
+                inner.array().get(index).map(|slot| &**slot)
+            } else {
+                pages.get(page_id - 1)
+                    .and_then(|page| page.get(index))
+                    .map(|slot| &**slot)
+            };
+
+            // # Safety
+            //
+            // registered values are only written with the thread list locked.
+            let init = slot.is_some_and(|slot| slot.value.with(|val| unsafe { (*val).is_some() }));
+
+            match (init, page_id) {
+                (false, _) => (),
+                (true, 0) => stats.fast_slots += 1,
+                (true, _) => stats.fallback_slots += 1
+            }
+        }
+
+        #[cfg(feature = "stats")]
+        {
+            let counters = &inner.counters;
+            let hits = |slot: &Slot<T>| slot.hits.load(Ordering::Relaxed);
+
+            stats.fast_hits = inner.array().iter().map(|slot| hits(slot)).sum();
+            stats.fallback_hits = pages.iter()
+                .flat_map(|page| page.iter())
+                .map(|slot| hits(slot))
+                .sum();
+            stats.inits = counters.inits.load(Ordering::Relaxed);
+            stats.exit_drops = counters.exit_drops.load(Ordering::Relaxed);
+            stats.releases = counters.releases.load(Ordering::Relaxed);
+        }
+
+        stats
+    }
+
+    /// Take values of all registered threads.
+    pub fn drain(&mut self) -> Vec<T> {
+        let tr = self.as_threads_ref();
+
+        let threads = {
+            let mut threads = self.inner.threads.lock().unwrap();
+            mem::take(&mut *threads)
+        };
+
+        let values = threads.into_iter()
+            // once removed from thread list, owner no longer touches the value.
+            .filter(|(_, handle)| handle.unregister(&tr))
+            .filter_map(|(id, _)| unsafe {
+                self.inner.mail.lock().unwrap().forward(id);
+                self.get(id)?.value.with_mut(|val| (*val).take())
+            })
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "stats")]
+        self.inner.counters.releases.fetch_add(values.len() as u64, Ordering::Relaxed);
+
+        values
+    }
+
+    /// Free trailing pages in which no thread holds a value.
+    pub fn shrink_to_fit(&mut self) {
+        let inner = &self.inner;
+        let end = inner.threads.lock()
+            .unwrap()
+            .keys()
+            .next_back()
+            .map_or(0, |id| id + 1);
+
+        // we have unique access to storage,
+        // so slots of unregistered threads are not in use.
+        Storage::free(Storage::truncate(inner, inner.array_len(), end));
+    }
+
+    /// Split off trailing pages after `exiting` thread drops its value.
+    ///
+    /// # Safety
+    ///
+    /// storage must be alive and the thread list must be locked.
+    unsafe fn trim_at_exit(trim: &Trim, threads: &BTreeMap<usize, ThreadHandle>, pool: &IdPool, exiting: usize)
+        -> Option<Freed>
+    {
+        let inner = &*trim.inner.cast::<Inner<T, A>>().as_ptr();
+
+        if map_index(trim.capacity, inner.page_size, exiting).0 == 0 {
+            return None
+        }
+
+        let end = threads.keys()
+            .next_back()
+            .map_or(0, |id| id + 1);
+
+        // hold id pool while splitting, so no thread can start using slots above `end`.
+        let freed = pool.with_live_end(exiting, |live_end| {
+            Storage::truncate(inner, trim.capacity, end.max(live_end))
+        });
+
+        if freed.is_empty() {
+            None
+        } else {
+            Some(Freed::new(freed))
+        }
+    }
+
+    /// Split off pages whose slots are all above `end`.
+    ///
+    /// Caller must ensure that no thread uses the slots above `end`.
+    fn truncate(inner: &Inner<T, A>, capacity: usize, end: usize) -> Vec<Page<T, A>> {
+        let keep = match end.checked_sub(1) {
+            Some(last) => map_index(capacity, inner.page_size, last).0,
+            None => 0
+        };
+
+        let mut pages = inner.fallback.lock().unwrap();
+
+        if pages.len() <= keep {
+            return Vec::new()
+        }
+
+        pages.split_off(keep)
+    }
+
+    /// Drop values left in `pages` and free them.
+    ///
+    /// Pages must be no longer reachable and the owners of their slots are gone.
+    fn free(pages: Vec<Page<T, A>>) {
+        let mut values = Vec::new();
+
+        for slot in pages.iter().flat_map(|page| page.ptr.array().iter()) {
+            // # Safety
+            //
+            // slot is no longer reachable and its owner is gone.
+            values.extend(slot.value.with_mut(|val| unsafe { (*val).take() }));
+        }
+
+        drop(values);
+        drop(pages);
+    }
+
+    /// Drop values of all slots, including those of exited threads.
+    ///
+    /// Only used by storage that does not register values to threads.
+    pub fn clear(&mut self) {
+        let inner = &self.inner;
+        let pages = inner.fallback.lock().unwrap();
+        let fast = inner.array()
+            .iter()
+            .map(|slot| &**slot);
+        let slow = pages.iter()
+            .flat_map(|page| page.ptr.array().iter().map(|slot| &**slot));
+
+        for slot in fast.chain(slow) {
+            // # Safety
+            //
+            // we have unique access to storage.
+            drop(slot.value.with_mut(|val| unsafe { (*val).take() }));
+        }
+    }
+
+    fn pages(inner: &Inner<T, A>) -> Vec<Array<'_, ManuallyDrop<Slot<T>>>> {
+        // pages are only freed with the thread list locked, or with unique access,
+        // so caller holding the thread list only needs the fallback lock while taking their address.
+        inner.fallback.lock()
+            .unwrap()
+            .iter()
+            .map(|page| unsafe { page.ptr.array().unbind() })
+            .collect()
+    }
+
+    fn pages_size(inner: &Inner<T, A>) -> usize {
+        inner.fallback.lock()
+            .unwrap()
+            .iter()
+            .map(|page| page.ptr.alloc_size() + mem::size_of_val(&*page.stamps))
+            .sum()
+    }
+
     #[cold]
-    unsafe fn or_get(inner: &Inner<T>, page_id: usize, index: usize) -> Option<&T> {
+    unsafe fn or_get(inner: &Inner<T, A>, page_id: usize, index: usize) -> Option<&Slot<T>> {
         let pages = inner.fallback.lock().unwrap();
-        pages.get(page_id - 1)?
+        let ptr = &**pages.get(page_id - 1)?
             .ptr
-            .get_unchecked(index)
-            .with(|obj| (*obj).as_ref())
+            .array()
+            .get_unchecked(index);
+
+        // pages are never freed while their threads are alive
+        Some(&*(ptr as *const Slot<T>))
     }
 
     #[cold]
-    unsafe fn or_new(inner: &Inner<T>, arr_len: usize, page_id: usize, index: usize)
-        -> NonNull<UnsafeCell<Option<T>>>
+    unsafe fn or_new(inner: &Inner<T, A>, page_id: usize, index: usize)
+        -> Option<NonNull<Slot<T>>>
     {
+        if page_id > inner.max_pages {
+            return None;
+        }
+
         let mut pages = inner.fallback.lock().unwrap();
         let page_id = page_id - 1;
 
-        if page_id >= pages.len() {
-            pages.resize_with(page_id + 1, || Page::new(arr_len));
+        while pages.len() <= page_id {
+            let len = page_len(inner.page_size, pages.len() + 1)?;
+
+            match Page::try_new_in(len, inner.padding, inner.eviction.is_some(), inner.alloc.clone()) {
+                Ok(page) => pages.push(page),
+                Err(AllocError::CapacityOverflow) => return None,
+                Err(AllocError::Failed(layout)) => {
+                    // unlock first, so the lock is not poisoned if it panics.
+                    drop(pages);
+                    handle_alloc_error(layout)
+                }
+            }
         }
 
         let ptr = pages.get_unchecked(page_id)
             .ptr
+            .array()
             .get_unchecked(index);
-        let ptr = &**ptr as *const UnsafeCell<Option<_>>;
-        NonNull::new_unchecked(ptr as *mut _)
+        let ptr = &**ptr as *const Slot<_>;
+        Some(NonNull::new_unchecked(ptr as *mut _))
     }
 }
 
-impl<T> Page<T> {
-    fn new(arr_len: usize) -> Page<T> {
-        let arr = (0..arr_len)
-            .map(|_| ManuallyDrop::new(UnsafeCell::new(None)))
-            .collect::<Vec<_>>();
-        Page { ptr: arr.into_boxed_slice() }
+impl<T> Slot<T> {
+    fn new() -> Slot<T> {
+        Slot {
+            value: UnsafeCell::new(None),
+            flags: AtomicU32::new(0),
+            session: Cell::new(0),
+
+            #[cfg(feature = "stats")]
+            hits: AtomicU64::new(0)
+        }
+    }
+
+    #[inline]
+    pub fn set_session(&self, session: u64) {
+        self.session.set(session as u32);
+    }
+
+    /// Whether value may have been handed out in `session`.
+    pub fn in_session(&self, session: u64) -> bool {
+        self.session.get() == session as u32
+    }
+}
+
+impl<T> Mail<T> {
+    /// Keep the messages that thread `id` never processed, so sender can take them back.
+    pub fn forward(&mut self, id: usize) {
+        if let Some(msgs) = self.pending.remove(&id) {
+            self.undelivered.extend(msgs);
+        }
+    }
+}
+
+impl<T, A: Allocator> Inner<T, A> {
+    #[cfg(feature = "std")]
+    #[inline]
+    fn now(&self) -> u64 {
+        u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn now(&self) -> u64 {
+        self.ticks.fetch_add(1, Ordering::Relaxed) + 1
     }
 }
 
-impl<T> Drop for Storage<T> {
+impl<T, A: Allocator> Page<T, A> {
+    fn try_new_in(arr_len: usize, padding: Padding, eviction: bool, alloc: A) -> Result<Page<T, A>, AllocError> {
+        let ptr = BoxTail::try_new_in(
+            (),
+            arr_len,
+            padding.align(),
+            |ptr: *mut ManuallyDrop<Slot<T>>| unsafe {
+                ptr.write(ManuallyDrop::new(Slot::new()));
+            },
+            alloc
+        )?;
+
+        Ok(Page {
+            ptr,
+            stamps: new_stamps(eviction, arr_len)
+        })
+    }
+}
+
+impl<T, A: Allocator + Clone> Drop for Storage<T, A> {
     fn drop(&mut self) {
         let tr = self.as_threads_ref();
 
@@ -131,27 +896,173 @@ impl<T> Drop for Storage<T> {
             mem::take(&mut *threads)
         };
         for thread in threads.values() {
-            unsafe {
-                thread.release(&tr);
+            let _released = unsafe {
+                thread.release(&tr)
+            };
+
+            #[cfg(feature = "stats")]
+            if _released {
+                self.inner.counters.releases.fetch_add(1, Ordering::Relaxed);
             }
         }
+
+        #[cfg(feature = "stats")]
+        {
+            let stats = self.stats();
+            tracing::debug!(
+                inits = stats.inits,
+                exit_drops = stats.exit_drops,
+                releases = stats.releases,
+                fast_hits = stats.fast_hits,
+                fallback_hits = stats.fallback_hits,
+                "thread-local storage dropped"
+            );
+        }
     }
 }
 
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for &id in &mut self.ids {
+            let (page_id, index) = map_index(self.array.len(), self.page_size, id);
+
+            let slot = if page_id == 0 {
+                &**self.array.get(index)?
+            } else {
+                &**self.pages.get(page_id - 1)?.get(index)?
+            };
+
+            // # Safety
+            //
+            // registered values are only written before registration,
+            // and only dropped after being removed with the thread list locked.
+            if let Some(val) = slot.value.with(|val| unsafe { (*val).as_ref() }) {
+                return Some((id, val));
+            }
+        }
+
+        None
+    }
+}
+
+/// Serial of new storage.
+///
+/// It is not a loom atomic, because it only orders storages and is never reset.
+fn next_serial() -> u64 {
+    use core::sync::atomic::AtomicU64;
+
+    static SERIAL: AtomicU64 = AtomicU64::new(0);
+    SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
 impl ThreadsRef {
-    pub unsafe fn remove(&self, id: usize) {
-        let mut threads = (*self.ptr.as_ptr()).lock().unwrap();
-        threads.remove(&id);
+    /// Remove thread `id` whose ids come from `pool`, calling `dtor` while the list is locked.
+    pub unsafe fn remove<F: FnOnce()>(&self, id: usize, pool: &IdPool, dtor: F) {
+        let freed = {
+            let mut threads = (*self.ptr.as_ptr()).lock().unwrap();
+            threads.remove(&id);
+            dtor();
+
+            self.trim.and_then(|trim| (trim.trim)(&trim, &threads, pool, id))
+        };
+
+        drop(freed);
+    }
+
+    /// Count value dropped at thread exit.
+    ///
+    /// # Safety
+    ///
+    /// storage must be alive.
+    #[cfg(feature = "stats")]
+    pub unsafe fn record_exit(&self) {
+        (*self.counters.as_ptr()).exit_drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Freed {
+    fn new<T, A: Allocator + Clone>(pages: Vec<Page<T, A>>) -> Freed {
+        unsafe fn free<T, A: Allocator + Clone>(ptr: NonNull<()>) {
+            let pages = Box::from_raw(ptr.cast::<Vec<Page<T, A>>>().as_ptr());
+            Storage::free(*pages);
+        }
+
+        Freed {
+            ptr: NonNull::from(Box::leak(Box::new(pages))).cast(),
+            free: free::<T, A>
+        }
     }
 }
 
+impl Drop for Freed {
+    fn drop(&mut self) {
+        // # Safety
+        //
+        // `ptr` is only freed here.
+        unsafe {
+            (self.free)(self.ptr);
+        }
+    }
+}
+
+// identify storage by its serial.
+impl PartialEq for ThreadsRef {
+    fn eq(&self, other: &ThreadsRef) -> bool {
+        self.serial == other.serial
+    }
+}
+
+impl Eq for ThreadsRef {}
+
+impl PartialOrd for ThreadsRef {
+    fn partial_cmp(&self, other: &ThreadsRef) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ThreadsRef {
+    fn cmp(&self, other: &ThreadsRef) -> CmpOrdering {
+        self.serial.cmp(&other.serial)
+    }
+}
+
+// # Safety
+//
+// storage ensures that the thread list outlives all tracked `ThreadsRef`.
+unsafe impl Send for ThreadsRef {}
+
+/// Map thread id to page and index in page.
+///
+/// Page 0 is the lock-free array, fallback pages double in size
+/// starting from `page_size`, so page count grows logarithmically with id.
 #[inline]
-fn map_index(cap: usize, n: usize) -> (usize, usize) {
+fn map_index(cap: usize, page_size: usize, n: usize) -> (usize, usize) {
     if n < cap {
         (0, n)
     } else {
-        let i = n / cap;
-        let rem = n % cap;
-        (i, rem)
+        let n = n - cap;
+
+        // page `k` starts at `page_size * (2^k - 1)`
+        let k = (usize::BITS - 1 - (n / page_size + 1).leading_zeros()) as usize;
+        let start = page_size * ((1 << k) - 1);
+        (k + 1, n - start)
+    }
+}
+
+/// Access stamps of `len` slots, empty without eviction policy.
+fn new_stamps(eviction: bool, len: usize) -> Box<[AtomicU64]> {
+    if eviction {
+        (0..len).map(|_| AtomicU64::new(0)).collect()
+    } else {
+        Box::default()
     }
 }
+
+/// Number of slots of fallback page `page_id`, `None` if it overflows.
+#[inline]
+pub fn page_len(page_size: usize, page_id: usize) -> Option<usize> {
+    let shift = u32::try_from(page_id - 1).ok()?;
+    1usize.checked_shl(shift)?.checked_mul(page_size)
+}
@@ -0,0 +1,78 @@
+use std::cell::RefCell;
+use std::sync::Mutex;
+use rayon::ThreadPool;
+use rayon::iter::{ IntoParallelIterator, ParallelIterator };
+use crate::ThreadLocal;
+
+
+impl<T: Send + 'static> ThreadLocal<T> {
+    /// Initialize the value of every worker of `pool`.
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use per_thread_object::ThreadLocal;
+    ///
+    /// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    /// let tl: ThreadLocal<Vec<u8>> = ThreadLocal::new();
+    ///
+    /// tl.init_on_pool(&pool, || Vec::with_capacity(1024));
+    ///
+    /// pool.install(|| {
+    ///     per_thread_object::stack_token!(token);
+    ///     assert!(tl.get(token).is_some());
+    /// });
+    /// ```
+    pub fn init_on_pool<F>(&self, pool: &ThreadPool, init: F)
+    where
+        F: Fn() -> T + Sync
+    {
+        pool.broadcast(|_| {
+            crate::stack_token!(token);
+            self.get_or_init(token, &init);
+        });
+    }
+
+    /// Fold items of parallel iterator into one accumulator per thread.
+    ///
+    /// Returns the accumulators, which need to be merged by caller.
+    ///
+    /// ```rust
+    /// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+    /// use rayon::prelude::*;
+    /// use per_thread_object::ThreadLocal;
+    ///
+    /// let sum: u64 = ThreadLocal::par_fold(0..1000u64, || 0, |acc, n| *acc += n).sum();
+    /// assert_eq!(499500, sum);
+    /// ```
+    pub fn par_fold<I, ID, F>(iter: I, init: ID, fold: F) -> impl Iterator<Item = T>
+    where
+        I: IntoParallelIterator,
+        ID: Fn() -> T + Sync + Send,
+        F: Fn(&mut T, I::Item) + Sync + Send
+    {
+        // workers of current pool and the thread that spawns them
+        let tl: ThreadLocal<RefCell<T>> = ThreadLocal::with_threads(rayon::current_num_threads() + 1);
+        let nested = Mutex::new(Vec::new());
+
+        iter.into_par_iter().for_each(|item| {
+            crate::stack_token!(token);
+
+            let acc = tl.get_or_init(token, || RefCell::new(init()));
+
+            match acc.try_borrow_mut() {
+                Ok(mut acc) => fold(&mut acc, item),
+                Err(_) => {
+                    // `fold` waits in rayon and this thread picked up another item,
+                    // so the accumulator of this thread is still borrowed.
+                    let mut acc = init();
+                    fold(&mut acc, item);
+                    nested.lock().unwrap().push(acc);
+                }
+            };
+        });
+
+        tl.into_iter()
+            .map(RefCell::into_inner)
+            .chain(nested.into_inner().unwrap())
+    }
+}
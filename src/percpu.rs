@@ -0,0 +1,187 @@
+use std::mem::ManuallyDrop;
+use std::sync::PoisonError;
+use crate::loom::sync::Mutex;
+use crate::util::BoxTail;
+use crate::allocator::Global;
+use crate::{ thread, Padding, OsThread };
+
+
+/// Per-CPU storage
+///
+/// Each slot belongs to a CPU rather than a thread,
+/// so memory is bounded by the number of CPUs no matter how many threads run.
+/// On Linux, slot is chosen by `sched_getcpu`, which reads rseq area when available.
+/// On other platforms, it falls back to the dense thread id.
+///
+/// Several threads can map to one slot, and a thread can migrate between CPUs,
+/// so values are only accessed by closure while the slot is locked.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use std::thread;
+/// use per_thread_object::PerCpu;
+///
+/// let counter: PerCpu<u64> = PerCpu::new();
+///
+/// thread::scope(|s| {
+///     for _ in 0..4 {
+///         s.spawn(|| counter.with(|| 0, |n| *n += 1));
+///     }
+/// });
+///
+/// let mut sum = 0;
+/// counter.for_each(|n| sum += *n);
+/// assert_eq!(4, sum);
+/// ```
+pub struct PerCpu<T> {
+    slots: BoxTail<(), ManuallyDrop<Mutex<Option<T>>>>
+}
+
+impl<T: Send> PerCpu<T> {
+    /// Create a `PerCpu` with one slot for each CPU.
+    pub fn new() -> PerCpu<T> {
+        PerCpu::with_slots(num_cpus())
+    }
+
+    /// Create a `PerCpu` with `num` slots, CPUs above it share slots.
+    ///
+    /// Panics if `num` is zero.
+    pub fn with_slots(num: usize) -> PerCpu<T> {
+        assert!(num > 0, "number of slots must be greater than zero");
+
+        let slots = BoxTail::new_in(
+            (),
+            num,
+            Padding::CacheLine.align(),
+            |ptr: *mut ManuallyDrop<Mutex<Option<T>>>| unsafe {
+                ptr.write(ManuallyDrop::new(Mutex::new(None)));
+            },
+            Global
+        );
+
+        PerCpu { slots }
+    }
+
+    /// Number of slots.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.array_len()
+    }
+
+    /// Always `false`, there is at least one slot.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Call `f` with the value of current CPU, initialize it with `init` if not exists.
+    ///
+    /// The slot stays locked while `f` runs, so other threads on same CPU wait for it.
+    /// Calling `with` of the same `PerCpu` inside `f` may deadlock.
+    ///
+    /// If `f` panics, the value is kept as `f` left it.
+    ///
+    /// With `fork` feature, `fork` on other threads waits until `f` returns,
+    /// see [`after_fork_child`](crate::after_fork_child).
+    pub fn with<I, F, R>(&self, init: I, f: F) -> R
+    where
+        I: FnOnce() -> T,
+        F: FnOnce(&mut T) -> R
+    {
+        let slots = self.slots.array();
+        let index = current_cpu() % slots.len();
+
+        // # Safety
+        //
+        // index is less than array length.
+        let slot = unsafe { slots.get_unchecked(index) };
+
+        // a panic in `f` only leaves the value partially updated, slot is still usable.
+        let mut val = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        f(val.get_or_insert_with(init))
+    }
+
+    /// Call `f` with the value of each slot in turn.
+    ///
+    /// Each slot is locked only while `f` visits it.
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&mut T)
+    {
+        for slot in self.slots.array().iter() {
+            if let Some(val) = slot.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+                f(val);
+            }
+        }
+    }
+}
+
+impl<T: Send> Default for PerCpu<T> {
+    #[inline]
+    fn default() -> PerCpu<T> {
+        PerCpu::new()
+    }
+}
+
+impl<T: Send> IntoIterator for PerCpu<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    /// Take the values of all slots.
+    fn into_iter(self) -> Self::IntoIter {
+        self.slots.array()
+            .iter()
+            .filter_map(|slot| slot.lock().unwrap_or_else(PoisonError::into_inner).take())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl<T> Drop for PerCpu<T> {
+    fn drop(&mut self) {
+        let slots = self.slots.array();
+
+        for index in 0..slots.len() {
+            // # Safety
+            //
+            // we have unique access and each slot is dropped once.
+            unsafe {
+                ManuallyDrop::drop(&mut *slots.as_ptr_unchecked(index));
+            }
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for PerCpu<T> {}
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+#[cfg(target_os = "linux")]
+#[cfg(not(feature = "loom"))]
+#[cfg(not(feature = "shuttle"))]
+#[inline]
+fn current_cpu() -> usize {
+    let cpu = unsafe { libc::sched_getcpu() };
+
+    if cpu >= 0 {
+        cpu as usize
+    } else {
+        thread::get::<OsThread>()
+    }
+}
+
+#[cfg(any(not(target_os = "linux"), feature = "loom", feature = "shuttle"))]
+#[inline]
+fn current_cpu() -> usize {
+    thread::get::<OsThread>()
+}
+
+fn num_cpus() -> usize {
+    let num = std::thread::available_parallelism()
+        .map_or(1, |num| num.get());
+
+    // cpu id can exceed the number of available cpus when affinity is restricted.
+    #[cfg(target_os = "linux")]
+    let num = num.max(unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(0) as usize);
+
+    num
+}
@@ -0,0 +1,177 @@
+use core::{ ops, fmt };
+use core::mem::ManuallyDrop;
+use alloc::vec::Vec;
+use crate::{ thread, ThreadLocal, OsThread };
+use crate::util::AtomicStack;
+use crate::loom::cell::UnsafeCell;
+use crate::loom::sync::Arc;
+use crate::loom::sync::atomic::{ AtomicBool, Ordering };
+
+
+/// Per-thread object pool
+///
+/// Each thread checks out objects from its own free list without locking.
+/// Objects dropped on another thread are returned to the owner thread
+/// through a lock-free queue, and are reused on its next checkout.
+///
+/// When the owner thread exits, its free list is released,
+/// and objects returned later will be dropped directly.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use per_thread_object::PerThreadPool;
+///
+/// let pool: PerThreadPool<Vec<u8>> = PerThreadPool::new();
+///
+/// let mut buf = pool.checkout(Vec::new);
+/// buf.extend_from_slice(b"hello");
+/// drop(buf);
+///
+/// let buf = pool.checkout(Vec::new);
+/// assert_eq!(b"hello", &buf[..]);
+/// ```
+pub struct PerThreadPool<T: Send + 'static> {
+    local: ThreadLocal<Local<T>>
+}
+
+/// Object checked out from [`PerThreadPool`].
+///
+/// It will be returned to the pool of its owner thread when dropped.
+pub struct Pooled<'a, T: Send + 'static> {
+    value: ManuallyDrop<T>,
+    shard: Arc<Shard<T>>,
+    _pool: &'a PerThreadPool<T>
+}
+
+struct Local<T>(Arc<Shard<T>>);
+
+struct Shard<T> {
+    owner: usize,
+    closed: AtomicBool,
+    free: UnsafeCell<Vec<T>>,
+    remote: AtomicStack<T>
+}
+
+impl<T: Send + 'static> PerThreadPool<T> {
+    pub fn new() -> PerThreadPool<T> {
+        PerThreadPool {
+            local: ThreadLocal::new()
+        }
+    }
+
+    pub fn with_threads(num: usize) -> PerThreadPool<T> {
+        PerThreadPool {
+            local: ThreadLocal::with_threads(num)
+        }
+    }
+
+    /// Take an object from the free list of current thread,
+    /// or create a new one with `create` if the list is empty.
+    pub fn checkout<F>(&self, create: F) -> Pooled<'_, T>
+    where
+        F: FnOnce() -> T
+    {
+        crate::stack_token!(token);
+
+        let Local(shard) = self.local.get_or_init(token, || Local(Arc::new(Shard {
+            owner: thread::get::<OsThread>(),
+            closed: AtomicBool::new(false),
+            free: UnsafeCell::new(Vec::new()),
+            remote: AtomicStack::new()
+        })));
+
+        // # Safety
+        //
+        // free list is only accessed by owner thread.
+        let value = shard.free.with_mut(|free| {
+            let free = unsafe { &mut *free };
+
+            if free.is_empty() && !shard.remote.is_empty() {
+                free.extend(shard.remote.take_all());
+            }
+
+            free.pop()
+        });
+
+        Pooled {
+            value: ManuallyDrop::new(value.unwrap_or_else(create)),
+            shard: Arc::clone(shard),
+            _pool: self
+        }
+    }
+}
+
+impl<T: Send + 'static> Pooled<'_, T> {
+    /// Take the object, it will not be returned to pool.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.value) };
+        let shard = unsafe { core::ptr::read(&this.shard) };
+        core::mem::forget(this);
+        drop(shard);
+        value
+    }
+}
+
+impl<T: Send + 'static> ops::Deref for Pooled<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Send + 'static> ops::DerefMut for Pooled<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Send + fmt::Debug + 'static> fmt::Debug for Pooled<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.value, f)
+    }
+}
+
+impl<T: Send + 'static> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        let value = unsafe { ManuallyDrop::take(&mut self.value) };
+        let shard = &*self.shard;
+
+        // shard is closed before owner thread releases its id,
+        // so if it is not closed, the thread with same id must be the owner.
+        if shard.closed.load(Ordering::Acquire) {
+            drop(value);
+        } else if thread::try_get::<OsThread>() == Some(shard.owner) {
+            shard.free.with_mut(|free| unsafe { &mut *free }.push(value));
+        } else {
+            shard.remote.push(value);
+        }
+    }
+}
+
+impl<T> Drop for Local<T> {
+    fn drop(&mut self) {
+        let shard = &*self.0;
+        shard.closed.store(true, Ordering::Release);
+
+        // # Safety
+        //
+        // local is dropped by owner thread at exit,
+        // or by storage when no `Pooled` borrowed the pool.
+        let free = shard.free.with_mut(|free| core::mem::take(unsafe { &mut *free }));
+        drop(free);
+        drop(shard.remote.take_all());
+    }
+}
+
+impl<T: Send + 'static> Default for PerThreadPool<T> {
+    #[inline]
+    fn default() -> PerThreadPool<T> {
+        PerThreadPool::new()
+    }
+}
+
+unsafe impl<T: Send> Send for Shard<T> {}
+unsafe impl<T: Send> Sync for Shard<T> {}
@@ -0,0 +1,137 @@
+use core::ptr;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use alloc::vec::Vec;
+use crate::{ ThreadLocal, ThreadId };
+use crate::util::Backoff;
+use crate::loom::sync::atomic::{ self, AtomicUsize, Ordering };
+
+
+/// Per-thread value that other threads can read consistently
+///
+/// Owner thread publishes value through a sequence lock in its own slot,
+/// so publishing never blocks and never contends with other threads.
+/// Readers retry if they race with publishing.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use per_thread_object::{ PerThreadPublished, ThreadId };
+///
+/// #[derive(Clone, Copy, PartialEq, Debug)]
+/// struct Status {
+///     busy: bool,
+///     processed: u64
+/// }
+///
+/// let status = PerThreadPublished::new();
+/// status.publish(Status { busy: true, processed: 42 });
+///
+/// let snapshot = status.snapshot_all();
+/// assert_eq!(vec![(ThreadId::current(), Status { busy: true, processed: 42 })], snapshot);
+/// ```
+pub struct PerThreadPublished<T: Copy + Send + 'static> {
+    local: ThreadLocal<Slot<T>>
+}
+
+struct Slot<T> {
+    thread: ThreadId,
+    seq: AtomicUsize,
+    value: UnsafeCell<T>
+}
+
+impl<T: Copy + Send + 'static> PerThreadPublished<T> {
+    pub fn new() -> PerThreadPublished<T> {
+        PerThreadPublished {
+            local: ThreadLocal::new()
+        }
+    }
+
+    pub fn with_threads(num: usize) -> PerThreadPublished<T> {
+        PerThreadPublished {
+            local: ThreadLocal::with_threads(num)
+        }
+    }
+
+    /// Publish value of current thread.
+    pub fn publish(&self, value: T) {
+        crate::stack_token!(token);
+
+        let mut init = false;
+        let slot = self.local.get_or_init(token, || {
+            init = true;
+            Slot {
+                thread: ThreadId::current(),
+                seq: AtomicUsize::new(0),
+                value: UnsafeCell::new(value)
+            }
+        });
+
+        if !init {
+            slot.write(value);
+        }
+    }
+
+    /// Read values of all threads.
+    ///
+    /// Each value is read consistently,
+    /// but values of different threads may be published at different time.
+    pub fn snapshot_all(&self) -> Vec<(ThreadId, T)> {
+        self.local.with_iter(|iter| {
+            iter.map(|(_, slot)| (slot.thread, slot.read()))
+                .collect()
+        })
+    }
+}
+
+impl<T: Copy> Slot<T> {
+    /// Only owner thread can write.
+    fn write(&self, value: T) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+
+        unsafe {
+            ptr::write_volatile(self.value.get(), value);
+        }
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    fn read(&self) -> T {
+        let backoff = Backoff::new();
+
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+
+            if seq & 1 == 0 {
+                // # Safety
+                //
+                // value may be torn by racing write,
+                // so read it as `MaybeUninit` and only use it if sequence is unchanged.
+                let value = unsafe {
+                    ptr::read_volatile(self.value.get().cast::<MaybeUninit<T>>())
+                };
+
+                atomic::fence(Ordering::Acquire);
+
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return unsafe { value.assume_init() };
+                }
+            }
+
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T: Copy + Send + 'static> Default for PerThreadPublished<T> {
+    #[inline]
+    fn default() -> PerThreadPublished<T> {
+        PerThreadPublished::new()
+    }
+}
+
+// # Safety
+//
+// value is only written by owner thread, and read through sequence lock.
+unsafe impl<T: Copy + Send> Sync for Slot<T> {}
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use tokio::runtime::Builder;
+use crate::ThreadLocal;
+
+
+/// A set of `ThreadLocal` bound to the threads of a tokio runtime.
+///
+/// Values are initialized when a runtime thread starts,
+/// and released when it stops, so they do not outlive the runtime.
+/// Note that tokio also calls the hooks on threads of blocking pool.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use std::sync::Arc;
+/// use per_thread_object::{ ThreadLocal, WorkerLocals };
+///
+/// let cache: Arc<ThreadLocal<Vec<u8>>> = Arc::new(ThreadLocal::new());
+///
+/// let mut locals = WorkerLocals::new();
+/// locals.register(cache.clone(), || Vec::with_capacity(1024));
+///
+/// let rt = locals.install(&mut tokio::runtime::Builder::new_multi_thread())
+///     .build()
+///     .unwrap();
+///
+/// rt.block_on(async {
+///     tokio::spawn(async move {
+///         cache.with(|val| assert!(val.is_some()));
+///     }).await.unwrap();
+/// });
+/// ```
+#[derive(Default)]
+pub struct WorkerLocals {
+    locals: Vec<Box<dyn WorkerLocal>>
+}
+
+trait WorkerLocal: Send + Sync {
+    fn start(&self);
+    fn stop(&self);
+}
+
+struct Entry<T: Send + 'static, F> {
+    tl: Arc<ThreadLocal<T>>,
+    init: F
+}
+
+impl WorkerLocals {
+    pub fn new() -> WorkerLocals {
+        WorkerLocals::default()
+    }
+
+    /// Add `tl` to the set, its value is built by `init` when thread starts.
+    pub fn register<T, F>(&mut self, tl: Arc<ThreadLocal<T>>, init: F) -> &mut WorkerLocals
+    where
+        T: Send + 'static,
+        F: Fn() -> T + Send + Sync + 'static
+    {
+        self.locals.push(Box::new(Entry { tl, init }));
+        self
+    }
+
+    /// Initialize all values of current thread.
+    pub fn on_thread_start(&self) {
+        for local in &self.locals {
+            local.start();
+        }
+    }
+
+    /// Release all values of current thread.
+    pub fn on_thread_stop(&self) {
+        for local in &self.locals {
+            local.stop();
+        }
+    }
+
+    /// Set `on_thread_start` and `on_thread_stop` hooks of `builder`.
+    pub fn install(self, builder: &mut Builder) -> &mut Builder {
+        let locals = Arc::new(self);
+        let locals2 = locals.clone();
+
+        builder
+            .on_thread_start(move || locals.on_thread_start())
+            .on_thread_stop(move || locals2.on_thread_stop())
+    }
+}
+
+impl<T, F> WorkerLocal for Entry<T, F>
+where
+    T: Send + 'static,
+    F: Fn() -> T + Send + Sync
+{
+    fn start(&self) {
+        crate::stack_token!(token);
+        self.tl.get_or_init(token, &self.init);
+    }
+
+    fn stop(&self) {
+        self.tl.release_current();
+    }
+}
@@ -0,0 +1,210 @@
+use core::{ ops, fmt };
+use core::marker::PhantomData;
+use alloc::vec::Vec;
+use crossbeam_utils::CachePadded;
+use crate::{ ThreadLocal, StackToken };
+use crate::util::Backoff;
+use crate::loom::cell::UnsafeCell;
+use crate::loom::sync::Arc;
+use crate::loom::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+
+
+/// Distributed reader-writer lock
+///
+/// Also known as big-reader lock.
+/// Each reader only marks its own per-thread slot,
+/// so readers do not bounce cache line between each other.
+/// Writer needs to scan slots of all threads, so writing is expensive.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use per_thread_object::ShardedRwLock;
+///
+/// let lock = ShardedRwLock::new(1);
+///
+/// {
+///     per_thread_object::stack_token!(token);
+///
+///     let r1 = lock.read(token);
+///     let r2 = lock.read(token);
+///     assert_eq!(2, *r1 + *r2);
+/// }
+///
+/// *lock.write() += 1;
+/// assert_eq!(2, lock.into_inner());
+/// ```
+pub struct ShardedRwLock<T> {
+    writer: AtomicBool,
+    readers: ThreadLocal<Reader>,
+    value: UnsafeCell<T>
+}
+
+pub struct ShardedRwLockReadGuard<'a, T> {
+    lock: &'a ShardedRwLock<T>,
+    reader: &'a Reader,
+    _marker: PhantomData<*const ()>
+}
+
+pub struct ShardedRwLockWriteGuard<'a, T> {
+    lock: &'a ShardedRwLock<T>,
+    _marker: PhantomData<*const ()>
+}
+
+struct Reader {
+    /// Shared with writer, so writer can wait for it without keeping the thread list locked.
+    count: Arc<CachePadded<AtomicUsize>>
+}
+
+impl<T> ShardedRwLock<T> {
+    pub fn new(value: T) -> ShardedRwLock<T> {
+        ShardedRwLock {
+            writer: AtomicBool::new(false),
+            readers: ThreadLocal::new(),
+            value: UnsafeCell::new(value)
+        }
+    }
+
+    pub fn with_threads(value: T, num: usize) -> ShardedRwLock<T> {
+        ShardedRwLock {
+            writer: AtomicBool::new(false),
+            readers: ThreadLocal::with_threads(num),
+            value: UnsafeCell::new(value)
+        }
+    }
+
+    /// Acquire read lock.
+    ///
+    /// Read lock is reentrant, but acquiring write lock while holding read lock will deadlock.
+    pub fn read<'a>(&'a self, token: &'a StackToken) -> ShardedRwLockReadGuard<'a, T> {
+        let reader = self.readers.get_or_init(token, || Reader {
+            count: Arc::new(CachePadded::new(AtomicUsize::new(0)))
+        });
+
+        let count = reader.count.load(Ordering::Relaxed);
+
+        if count == 0 {
+            let backoff = Backoff::new();
+
+            loop {
+                reader.count.store(1, Ordering::SeqCst);
+
+                if !self.writer.load(Ordering::SeqCst) {
+                    break
+                }
+
+                reader.count.store(0, Ordering::Release);
+
+                while self.writer.load(Ordering::Relaxed) {
+                    backoff.snooze();
+                }
+            }
+        } else {
+            // we already hold read lock, so no writer can be holding it.
+            reader.count.store(count + 1, Ordering::Relaxed);
+        }
+
+        ShardedRwLockReadGuard {
+            lock: self,
+            reader,
+            _marker: PhantomData
+        }
+    }
+
+    /// Acquire write lock.
+    ///
+    /// This will wait for readers of all threads.
+    pub fn write(&self) -> ShardedRwLockWriteGuard<'_, T> {
+        let backoff = Backoff::new();
+
+        while self.writer.compare_exchange_weak(false, true, Ordering::SeqCst, Ordering::Relaxed).is_err() {
+            backoff.snooze();
+        }
+
+        // wait without holding the thread list,
+        // since readers need it to register and to exit.
+        // readers that register later see `writer` and back off.
+        let readers = self.readers.with_iter(|iter| {
+            iter.map(|(_, reader)| Arc::clone(&reader.count))
+                .collect::<Vec<_>>()
+        });
+
+        for count in readers {
+            while count.load(Ordering::SeqCst) != 0 {
+                backoff.snooze();
+            }
+        }
+
+        ShardedRwLockWriteGuard {
+            lock: self,
+            _marker: PhantomData
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.with_mut(|val| unsafe { &mut *val })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T> ops::Deref for ShardedRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.lock.value.with(|val| unsafe { &*val })
+    }
+}
+
+impl<T> Drop for ShardedRwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let count = self.reader.count.load(Ordering::Relaxed);
+        self.reader.count.store(count - 1, Ordering::Release);
+    }
+}
+
+impl<T> ops::Deref for ShardedRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.lock.value.with(|val| unsafe { &*val })
+    }
+}
+
+impl<T> ops::DerefMut for ShardedRwLockWriteGuard<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.lock.value.with_mut(|val| unsafe { &mut *val })
+    }
+}
+
+impl<T> Drop for ShardedRwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.writer.store(false, Ordering::Release);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ShardedRwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ShardedRwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: Default> Default for ShardedRwLock<T> {
+    #[inline]
+    fn default() -> ShardedRwLock<T> {
+        ShardedRwLock::new(T::default())
+    }
+}
+
+unsafe impl<T: Send> Send for ShardedRwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for ShardedRwLock<T> {}
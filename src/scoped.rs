@@ -0,0 +1,139 @@
+use core::marker::PhantomData;
+use crate::page::{ Storage, Slot };
+use crate::thread::ThreadId;
+use crate::{ StackToken, PAGE_OVERFLOW };
+
+
+/// Per-object thread-local storage for values that borrow from the environment.
+///
+/// Unlike `ThreadLocal`, values are not registered to the thread,
+/// so they are not dropped at thread exit.
+/// Instead all values are dropped when `ScopedThreadLocal` is dropped,
+/// which is always before `'env` ends.
+///
+/// If a thread exits and its id is reused by a new thread,
+/// the old value is dropped by the new thread when it initializes its own value.
+///
+/// ```rust
+/// # if cfg!(feature = "loom") || cfg!(feature = "shuttle") { return }
+/// use std::thread;
+/// use std::cell::RefCell;
+/// use per_thread_object::ScopedThreadLocal;
+///
+/// let words = vec!["a", "b", "c"];
+/// let tl: ScopedThreadLocal<'_, RefCell<Vec<&str>>> = ScopedThreadLocal::new();
+///
+/// thread::scope(|s| {
+///     for word in &words {
+///         let tl = &tl;
+///         s.spawn(move || {
+///             per_thread_object::stack_token!(token);
+///             tl.get_or_init(token, Default::default).borrow_mut().push(word);
+///         });
+///     }
+/// });
+///
+/// drop(tl);
+/// ```
+pub struct ScopedThreadLocal<'env, T: Send + 'env> {
+    pool: Storage<(u64, T)>,
+    _marker: PhantomData<&'env ()>
+}
+
+impl<'env, T: Send + 'env> ScopedThreadLocal<'env, T> {
+    pub fn new() -> ScopedThreadLocal<'env, T> {
+        ScopedThreadLocal::with_threads(crate::default_threads())
+    }
+
+    pub fn with_threads(num: usize) -> ScopedThreadLocal<'env, T> {
+        ScopedThreadLocal {
+            pool: Storage::with_threads(num),
+            _marker: PhantomData
+        }
+    }
+
+    #[inline]
+    pub fn get<'stack>(&'stack self, _token: &'stack StackToken) -> Option<&'stack T> {
+        let thread = ThreadId::current();
+        let slot = unsafe { self.pool.get(thread.index())? };
+
+        match slot.value.with(|val| unsafe { (*val).as_ref() }) {
+            Some((serial, val)) if *serial == thread.serial() => Some(val),
+            _ => None
+        }
+    }
+
+    #[inline]
+    pub fn get_or_init<'stack, F>(&'stack self, token: &'stack StackToken, init: F)
+        -> &'stack T
+    where
+        F: FnOnce() -> T
+    {
+        use core::convert::Infallible;
+
+        match self.get_or_try_init::<_, Infallible>(token, || Ok(init())) {
+            Ok(val) => val,
+            Err(err) => match err {}
+        }
+    }
+
+    #[inline]
+    pub fn get_or_try_init<'stack, F, E>(&'stack self, _token: &'stack StackToken, init: F)
+        -> Result<&'stack T, E>
+    where
+        F: FnOnce() -> Result<T, E>
+    {
+        let thread = ThreadId::current();
+        let ptr = unsafe { self.pool.get_or_new(thread.index()) }
+            .unwrap_or_else(|| panic!("{}", PAGE_OVERFLOW));
+        let slot = unsafe { &*ptr.as_ptr() };
+
+        match slot.value.with(|val| unsafe { (*val).as_ref() }) {
+            Some((serial, val)) if *serial == thread.serial() => Ok(val),
+            _ => {
+                let newval = init()?;
+                Ok(ScopedThreadLocal::or_insert(slot, thread.serial(), newval))
+            }
+        }
+    }
+
+    #[cold]
+    fn or_insert(slot: &Slot<(u64, T)>, serial: u64, newval: T) -> &T {
+        // # Safety
+        //
+        // slot is only accessed by the thread that currently owns this id.
+        let (val, old) = slot.value.with_mut(|val| unsafe {
+            let val = &mut *val;
+
+            // `init` may have initialized value reentrantly
+            let old = match val {
+                Some((owner, _)) if *owner == serial => Some(newval),
+                _ => val.replace((serial, newval))
+                    .map(|(_, old)| old)
+            };
+
+            (&val.as_ref().unwrap().1, old)
+        });
+
+        // value of exited thread, or the one built by outer `init`.
+        drop(old);
+
+        val
+    }
+}
+
+impl<'env, T: Send + 'env> Default for ScopedThreadLocal<'env, T> {
+    #[inline]
+    fn default() -> ScopedThreadLocal<'env, T> {
+        ScopedThreadLocal::new()
+    }
+}
+
+impl<'env, T: Send + 'env> Drop for ScopedThreadLocal<'env, T> {
+    fn drop(&mut self) {
+        self.pool.clear();
+    }
+}
+
+unsafe impl<'env, T: Send + 'env> Send for ScopedThreadLocal<'env, T> {}
+unsafe impl<'env, T: Send + 'env> Sync for ScopedThreadLocal<'env, T> {}
@@ -1,9 +1,16 @@
-use std::ptr::NonNull;
-use std::cmp::Reverse;
-use std::collections::{ HashMap, BinaryHeap };
-use crate::page::ThreadsRef;
+use core::ptr::NonNull;
+use core::cell::Cell;
+use core::cmp::Reverse;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::{ BTreeMap, BTreeSet, BinaryHeap };
+use crate::StackToken;
+use crate::page::{ ThreadsRef, Slot, Mail };
 use crate::loom::sync::{ Arc, Mutex };
-use crate::loom::cell::UnsafeCell;
+use crate::loom::sync::atomic::{ AtomicBool, Ordering };
+
+#[cfg(not(feature = "std"))]
+use crate::loom::sync::atomic::AtomicPtr;
 
 #[cfg(feature = "loom")]
 use loom::{ thread_local, lazy_static };
@@ -11,136 +18,718 @@ use loom::{ thread_local, lazy_static };
 #[cfg(feature = "shuttle")]
 use shuttle::{ thread_local, lazy_static };
 
-pub struct ThreadHandle(Arc<Mutex<HashMap<ThreadsRef, Dtor>>>);
+#[derive(Clone)]
+pub struct ThreadHandle {
+    list: Arc<ThreadList>,
+    serial: u64
+}
+
+/// Thread identifier
+///
+/// `index` is the dense id used to find slot of thread,
+/// it will be reused after thread exits.
+/// `serial` is unique for the lifetime of process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId {
+    index: usize,
+    serial: u64
+}
+
+/// Allocator of thread ids, shared by all storages.
+///
+/// Each context keeps the pool it took its id from,
+/// so dropping a context never reaches the global,
+/// which is already gone when loom drops the contexts at the end of an execution.
+pub struct IdPool(PoolRef);
+
+#[cfg(not(feature = "loom"))]
+#[cfg(not(feature = "shuttle"))]
+type PoolRef = &'static Mutex<ThreadIdPool>;
+
+#[cfg(any(feature = "loom", feature = "shuttle"))]
+type PoolRef = Arc<Mutex<ThreadIdPool>>;
 
 #[cfg(not(feature = "loom"))]
 #[cfg(not(feature = "shuttle"))]
 static THREAD_ID_POOL: Mutex<ThreadIdPool> = Mutex::new(ThreadIdPool::new());
 
+// model checkers create it again for each execution.
 #[cfg(any(feature = "loom", feature = "shuttle"))]
 lazy_static! {
-    static ref THREAD_ID_POOL: Mutex<ThreadIdPool> = Mutex::new(ThreadIdPool::new());
+    static ref THREAD_ID_POOL: Arc<Mutex<ThreadIdPool>> = Arc::new(Mutex::new(ThreadIdPool::new()));
 }
 
+#[cfg(feature = "std")]
 thread_local!{
-    static THREAD_STATE: ThreadState = ThreadState::new();
+    static THREAD_STATE: Context = Context::new_os();
+}
+
+/// Provider registered by `set_current_context`, null until then.
+#[cfg(not(feature = "std"))]
+static CURRENT_CONTEXT: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Statistics of thread ids, see [`thread_stats`](crate::thread_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ThreadStats {
+    /// Number of thread ids ever allocated, which is the max slot index plus one.
+    pub max: usize,
+
+    /// Number of thread ids released by exited threads and waiting for reuse.
+    pub free: usize
 }
 
 struct ThreadIdPool {
     max: usize,
+    serial: u64,
     pool: Option<BinaryHeap<Reverse<usize>>>,
+
+    /// Ids in use, so the end of them is found without scanning free ids.
+    live: BTreeSet<usize>,
+
+    /// Lists of live OS threads, so the child of `fork` can find the threads that did not survive.
+    #[cfg(all(unix, feature = "std"))]
+    threads: BTreeMap<usize, Arc<ThreadList>>
 }
 
-struct ThreadState {
+/// Execution context that owns thread-local values.
+///
+/// It holds the dense id used to find slots,
+/// and the values registered by this context, which are dropped when it is dropped.
+/// Each OS thread has one implicitly, see [`OsThread`].
+///
+/// A custom context, such as a fiber, creates one with [`Context::new`]
+/// and provides it with [`ThreadIdentity`].
+pub struct Context {
     id: usize,
-    list: Arc<Mutex<HashMap<ThreadsRef, Dtor>>>
+    serial: u64,
+    pool: IdPool,
+
+    /// Whether `StackToken` is tracked, only for OS threads.
+    ///
+    /// Custom context processes its requests in `quiesce` instead.
+    os: bool,
+
+    /// Number of live `StackToken` that have accessed a value on this thread.
+    depth: Cell<usize>,
+
+    /// Incremented when the outermost `StackToken` accesses its first value.
+    ///
+    /// References handed out in previous session are all dead,
+    /// because they cannot outlive their token.
+    session: Cell<u64>,
+
+    list: Arc<ThreadList>
+}
+
+struct ThreadList {
+    dtors: Mutex<BTreeMap<ThreadsRef, Dtor>>,
+
+    /// Set when some storage requests to evict value of this thread.
+    evict: AtomicBool
 }
 
 struct Dtor {
     ptr: NonNull<()>,
-    drop: unsafe fn(*mut ())
+    id: usize,
+    mail: NonNull<()>,
+    drop: unsafe fn(*mut (), usize, *const ()),
+    take: unsafe fn(*mut (), usize, *const ()) -> Option<Box<dyn Send>>,
+    evict: bool
 }
 
 impl ThreadIdPool {
     const fn new() -> ThreadIdPool {
         ThreadIdPool {
             max: 0,
-            pool: None
+            serial: 0,
+            pool: None,
+            live: BTreeSet::new(),
+
+            #[cfg(all(unix, feature = "std"))]
+            threads: BTreeMap::new()
         }
     }
 
     fn alloc(&mut self) -> usize {
-        if let Some(Reverse(id)) = self.pool.get_or_insert_with(BinaryHeap::new).pop() {
+        let id = if let Some(Reverse(id)) = self.pool.get_or_insert_with(BinaryHeap::new).pop() {
             id
         } else {
             let id = self.max;
             self.max = id.checked_add(1).expect("thread id overflow");
             id
-        }
+        };
+
+        self.live.insert(id);
+        id
+    }
+
+    fn next_serial(&mut self) -> u64 {
+        let serial = self.serial;
+        self.serial = serial.checked_add(1).expect("thread serial overflow");
+        serial
     }
 
     fn dealloc(&mut self, id: usize) {
+        #[cfg(all(unix, feature = "std"))]
+        self.threads.remove(&id);
+
+        self.live.remove(&id);
         self.pool.get_or_insert_with(BinaryHeap::new).push(Reverse(id));
     }
+
+    /// End of the ids in use, ignoring `exiting`.
+    fn live_end(&self, exiting: usize) -> usize {
+        self.live.iter()
+            .rev()
+            .find(|&&id| id != exiting)
+            .map_or(0, |id| id + 1)
+    }
+
+    fn stats(&self) -> ThreadStats {
+        ThreadStats {
+            max: self.max,
+            free: self.pool.as_ref().map_or(0, BinaryHeap::len)
+        }
+    }
 }
 
-impl ThreadState {
-    fn new() -> ThreadState {
-        ThreadState {
-            id: THREAD_ID_POOL.lock().unwrap().alloc(),
-            list: Arc::new(Mutex::new(HashMap::new()))
+/// Provider of the current [`Context`].
+///
+/// `ThreadLocal` finds the slot of caller by the id of current context,
+/// and registers values to it, so they are dropped when the context is dropped.
+///
+/// # Safety
+///
+/// `with_current` must pass the context which is running the caller,
+/// and a context must never run on two threads at the same time.
+pub unsafe trait ThreadIdentity: 'static {
+    fn with_current<F, R>(f: F) -> R
+    where
+        F: FnOnce(&Context) -> R;
+
+    /// Same as `with_current`, but returns `None` if current context has been destroyed.
+    #[inline]
+    fn try_with_current<F, R>(f: F) -> Option<R>
+    where
+        F: FnOnce(&Context) -> R
+    {
+        Some(Self::with_current(f))
+    }
+}
+
+/// Identity of OS threads, which is the default.
+///
+/// Without `std` feature, it is the context returned by
+/// the provider registered with `set_current_context`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsThread;
+
+#[cfg(feature = "std")]
+unsafe impl ThreadIdentity for OsThread {
+    #[inline]
+    fn with_current<F, R>(f: F) -> R
+    where
+        F: FnOnce(&Context) -> R
+    {
+        THREAD_STATE.with(f)
+    }
+
+    #[inline]
+    fn try_with_current<F, R>(f: F) -> Option<R>
+    where
+        F: FnOnce(&Context) -> R
+    {
+        THREAD_STATE.try_with(f).ok()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+unsafe impl ThreadIdentity for OsThread {
+    #[inline]
+    fn with_current<F, R>(f: F) -> R
+    where
+        F: FnOnce(&Context) -> R
+    {
+        Self::try_with_current(f)
+            .expect("no context for current thread, see `set_current_context`")
+    }
+
+    #[inline]
+    fn try_with_current<F, R>(f: F) -> Option<R>
+    where
+        F: FnOnce(&Context) -> R
+    {
+        let current = CURRENT_CONTEXT.load(Ordering::Acquire);
+
+        if current.is_null() {
+            return None;
         }
+
+        // # Safety
+        //
+        // only `set_current_context` stores a non-null pointer, which is a provider function.
+        let current: fn() -> *const Context = unsafe { core::mem::transmute(current) };
+
+        // # Safety
+        //
+        // the provider guarantees that the context is alive while current thread runs.
+        unsafe { current().as_ref() }.map(f)
+    }
+}
+
+/// Register the provider of current thread context, used by [`OsThread`] without `std` feature.
+///
+/// The runtime creates a [`Context`] for each thread or task with [`Context::new_thread`],
+/// and drops it when that thread exits, which drops the values of the thread.
+/// `current` returns the context of the running thread,
+/// or null if it has not been created or has been destroyed.
+///
+/// With `std` feature, which another crate may enable,
+/// `OsThread` uses the thread locals of `std` and the provider is ignored.
+///
+/// # Safety
+///
+/// The returned context must stay alive until current thread stops running,
+/// and a context must never be returned on two threads at the same time.
+pub unsafe fn set_current_context(current: fn() -> *const Context) {
+    #[cfg(not(feature = "std"))]
+    CURRENT_CONTEXT.store(current as *mut (), Ordering::Release);
+
+    #[cfg(feature = "std")]
+    let _ = current;
+}
+
+impl Context {
+    /// Create a context with a new id.
+    ///
+    /// Values registered to it are dropped when it is dropped,
+    /// and its id is reused after that.
+    pub fn new() -> Context {
+        Context::alloc(false)
+    }
+
+    /// Create a context for a thread or task, which is provided by [`set_current_context`].
+    ///
+    /// Unlike [`Context::new`], values are evicted when the outermost `StackToken` is used.
+    pub fn new_thread() -> Context {
+        Context::new_os()
+    }
+
+    fn new_os() -> Context {
+        #[cfg(feature = "fork")]
+        crate::fork::register();
+
+        Context::alloc(true)
+    }
+
+    fn alloc(os: bool) -> Context {
+        let list = Arc::new(ThreadList {
+            dtors: Mutex::new(BTreeMap::new()),
+            evict: AtomicBool::new(false)
+        });
+        let ids = id_pool();
+
+        let (id, serial) = {
+            let mut pool = ids.0.lock().unwrap();
+            let id = pool.alloc();
+
+            #[cfg(all(unix, feature = "std"))]
+            if os {
+                pool.threads.insert(id, Arc::clone(&list));
+            }
+
+            (id, pool.next_serial())
+        };
+
+        Context {
+            id,
+            serial,
+            pool: ids,
+            os,
+            depth: Cell::new(0),
+            session: Cell::new(0),
+            list
+        }
+    }
+
+    /// Identifier of this context.
+    #[inline]
+    pub fn thread_id(&self) -> ThreadId {
+        ThreadId {
+            index: self.id,
+            serial: self.serial
+        }
+    }
+
+    /// Start a new session and process eviction requests of this context.
+    ///
+    /// Values are only invalidated or evicted at this point for custom context.
+    ///
+    /// # Safety
+    ///
+    /// No reference handed out to this context can be alive,
+    /// e.g. the scheduler calls it before resuming a fiber that holds no `StackToken`.
+    pub unsafe fn quiesce(&self) {
+        self.session.set(self.session.get().wrapping_add(1));
+        self.evict();
+    }
+
+    #[inline]
+    fn enter(&self, token: &StackToken) {
+        let depth = self.depth.get();
+
+        self.depth.set(depth + 1);
+        token.context.set(Some(NonNull::from(self)));
+
+        if depth == 0 {
+            self.session.set(self.session.get().wrapping_add(1));
+
+            if self.list.evict.load(Ordering::Relaxed) {
+                self.evict();
+            }
+        }
+    }
+
+    /// Evict values that storage requested.
+    ///
+    /// Only called when no `StackToken` on this thread has accessed a value,
+    /// so no reference to the values can be alive.
+    #[cold]
+    fn evict(&self) {
+        if !self.list.evict.swap(false, Ordering::Acquire) {
+            return
+        }
+
+        let values = {
+            let mut dtors = self.list.dtors.lock().unwrap();
+            let victims = dtors.iter()
+                .filter(|(_, dtor)| dtor.evict)
+                .map(|(tr, _)| *tr)
+                .collect::<Vec<_>>();
+
+            victims.into_iter()
+                .filter_map(|tr| {
+                    let dtor = dtors.remove(&tr)?;
+                    let mut value = None;
+
+                    // # Safety
+                    //
+                    // same as thread exit, storage cannot be freed while we hold the list.
+                    unsafe {
+                        tr.remove(self.id, &self.pool, || value = dtor.take());
+                    }
+
+                    value
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // values are moved out of storage,
+        // so drop them without holding any lock.
+        drop(values);
     }
 }
 
 impl Dtor {
-    fn new<T: 'static>(ptr: NonNull<UnsafeCell<Option<T>>>) -> Dtor {
-        unsafe fn try_drop<T: 'static>(ptr: *mut ()) {
-            let obj = &mut *ptr.cast::<UnsafeCell<Option<T>>>();
-            obj.with_mut(|val| {
+    fn new<T: Send + 'static>(ptr: NonNull<Slot<T>>, id: usize, mail: NonNull<Mutex<Mail<T>>>) -> Dtor {
+        unsafe fn forward<T: Send + 'static>(id: usize, mail: *const ()) {
+            (*mail.cast::<Mutex<Mail<T>>>()).lock()
+                .unwrap()
+                .forward(id);
+        }
+
+        unsafe fn try_drop<T: Send + 'static>(ptr: *mut (), id: usize, mail: *const ()) {
+            let slot = &*ptr.cast::<Slot<T>>();
+            slot.value.with_mut(|val| {
                 let _ = { &mut *val }.take();
             });
+            forward::<T>(id, mail);
+        }
+
+        unsafe fn take<T: Send + 'static>(ptr: *mut (), id: usize, mail: *const ()) -> Option<Box<dyn Send>> {
+            let slot = &*ptr.cast::<Slot<T>>();
+            let val = slot.value.with_mut(|val| { &mut *val }.take());
+            forward::<T>(id, mail);
+
+            val.map(|val| Box::new(val) as Box<dyn Send>)
         }
 
         Dtor {
             ptr: ptr.cast(),
-            drop: try_drop::<T>
+            id,
+            mail: mail.cast(),
+            drop: try_drop::<T>,
+            take: take::<T>,
+            evict: false
         }
     }
 
+    /// Drop value, and keep the messages still pending for it as undelivered.
     unsafe fn drop(&self) {
-        (self.drop)(self.ptr.as_ptr())
+        (self.drop)(self.ptr.as_ptr(), self.id, self.mail.as_ptr())
+    }
+
+    /// Take value, and keep the messages still pending for it as undelivered.
+    unsafe fn take(&self) -> Option<Box<dyn Send>> {
+        (self.take)(self.ptr.as_ptr(), self.id, self.mail.as_ptr())
+    }
+}
+
+// # Safety
+//
+// `Dtor` is only called by its owner thread on exit,
+// or by storage after removing it from the owner's list.
+unsafe impl Send for Dtor {}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context::new()
     }
 }
 
-impl Drop for ThreadState {
+impl Drop for Context {
     fn drop(&mut self) {
-        let mut list = self.list.lock().unwrap();
+        let mut list = self.list.dtors.lock().unwrap();
 
-        for (tr, dtor) in list.drain() {
-            unsafe {
-                dtor.drop();
+        #[cfg(feature = "stats")]
+        tracing::trace!(thread = self.id, serial = self.serial, values = list.len(), "thread exit");
 
+        for (tr, dtor) in core::mem::take(&mut *list) {
+            unsafe {
                 // # Safety
                 //
                 // because storage will ensure that all tracked `ThreadsRef` are valid.
-                tr.remove(self.id);
+                //
+                // value is dropped while the thread list is locked,
+                // so storage iteration never observes a value being dropped.
+                tr.remove(self.id, &self.pool, || {
+                    #[cfg(feature = "stats")]
+                    tr.record_exit();
+
+                    dtor.drop();
+                });
             }
         }
 
         drop(list);
 
-        THREAD_ID_POOL.lock().unwrap()
+        self.pool.0.lock().unwrap()
             .dealloc(self.id);
     }
 }
 
+impl ThreadId {
+    /// Identifier of current thread.
+    #[inline]
+    pub fn current() -> ThreadId {
+        OsThread::with_current(Context::thread_id)
+    }
+
+    /// Dense index of thread, it will be reused after thread exits.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Unique serial number of thread.
+    #[inline]
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+}
+
 impl ThreadHandle {
-    pub unsafe fn release(&self, tr: &ThreadsRef) {
+    #[inline]
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    /// Ask owner thread to evict its value the next time it touches the crate.
+    pub fn request_evict(&self, tr: &ThreadsRef) {
+        let mut dtors = self.list.dtors.lock().unwrap();
+
+        if let Some(dtor) = dtors.get_mut(tr) {
+            dtor.evict = true;
+            self.list.evict.store(true, Ordering::Release);
+        }
+    }
+
+    /// Drop value of thread.
+    ///
+    /// Returns `false` if the owner has already dropped the value.
+    pub unsafe fn release(&self, tr: &ThreadsRef) -> bool {
         let dtor = {
-            self.0.lock()
+            self.list.dtors.lock()
                 .unwrap()
                 .remove(tr)
         };
 
-        if let Some(dtor) = dtor {
-            dtor.drop();
+        match dtor {
+            Some(dtor) => {
+                dtor.drop();
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Remove value from thread list without dropping it.
+    ///
+    /// Returns `false` if the owner has already dropped the value.
+    pub fn unregister(&self, tr: &ThreadsRef) -> bool {
+        self.list.dtors.lock()
+            .unwrap()
+            .remove(tr)
+            .is_some()
+    }
+}
+
+pub fn stats() -> ThreadStats {
+    id_pool().0.lock().unwrap().stats()
+}
+
+#[cfg(not(feature = "loom"))]
+#[cfg(not(feature = "shuttle"))]
+#[inline]
+fn id_pool() -> IdPool {
+    IdPool(&THREAD_ID_POOL)
+}
+
+#[cfg(any(feature = "loom", feature = "shuttle"))]
+fn id_pool() -> IdPool {
+    IdPool(Arc::clone(&THREAD_ID_POOL))
+}
+
+impl IdPool {
+    /// Call `f` with the end of the ids in use by threads other than `exiting`.
+    ///
+    /// No thread can take an id until `f` returns.
+    pub fn with_live_end<R, F: FnOnce(usize) -> R>(&self, exiting: usize, f: F) -> R {
+        let pool = self.0.lock().unwrap();
+        f(pool.live_end(exiting))
+    }
+}
+
+/// Release the ids and values of OS threads that did not survive `fork`.
+///
+/// # Safety
+///
+/// Only called in the child process,
+/// and no thread could hold a lock of this crate when `fork` was called.
+#[cfg(all(unix, feature = "std"))]
+pub unsafe fn reap_orphans(leak: bool) {
+    let current = OsThread::try_with_current(|state| state.id);
+    let ids = &id_pool();
+
+    let orphans = {
+        let mut pool = ids.0.lock().unwrap();
+        let mut orphans = core::mem::take(&mut pool.threads);
+
+        if let Some(id) = current {
+            if let Some(list) = orphans.remove(&id) {
+                pool.threads.insert(id, list);
+            }
+        }
+
+        orphans
+    };
+
+    for (&id, list) in orphans.iter() {
+        let mut dtors = list.dtors.lock().unwrap();
+
+        for (tr, dtor) in core::mem::take(&mut *dtors) {
+            // # Safety
+            //
+            // same as thread exit, storage cannot be freed while we hold the list.
+            tr.remove(id, ids, || if leak {
+                core::mem::forget(dtor.take());
+            } else {
+                dtor.drop();
+            });
         }
     }
+
+    // dealloc after all values are gone,
+    // otherwise a trim could free the pages of an orphan that still holds a value.
+    let mut pool = ids.0.lock().unwrap();
+    for &id in orphans.keys() {
+        pool.dealloc(id);
+    }
 }
 
 #[inline]
-pub fn get() -> usize {
-    THREAD_STATE.with(|state| state.id)
+pub fn get<I: ThreadIdentity>() -> usize {
+    I::with_current(|state| state.id)
 }
 
-pub unsafe fn push<T: 'static>(tr: ThreadsRef, ptr: NonNull<UnsafeCell<Option<T>>>) -> ThreadHandle {
-    let dtor = Dtor::new(ptr);
+/// Called when `token` is used to access a value,
+/// returns id and session of current context.
+///
+/// Token is only tracked from its first access,
+/// because a token that never accessed a value cannot keep a reference alive.
+#[inline]
+pub fn enter<I: ThreadIdentity>(token: &StackToken) -> (usize, u64) {
+    if token.context.get().is_some() {
+        return I::with_current(|state| (state.id, state.session.get()));
+    }
+
+    I::with_current(|state| {
+        if state.os {
+            state.enter(token);
+        }
 
-    THREAD_STATE.with(|state| {
-        state.list.lock()
+        (state.id, state.session.get())
+    })
+}
+
+/// Process eviction requests of current thread now, if no `StackToken` has accessed a value.
+pub fn try_evict<I: ThreadIdentity>() {
+    let _ = I::try_with_current(|state| {
+        if state.os && state.depth.get() == 0 && state.list.evict.load(Ordering::Relaxed) {
+            // values dropped by evict may use tokens
+            state.depth.set(1);
+            state.evict();
+            state.depth.set(0);
+        }
+    });
+}
+
+/// Called when a token that has accessed a value is dropped.
+///
+/// # Safety
+///
+/// `cx` is the context that `enter` recorded in the token,
+/// which outlives the token because both belong to current thread.
+#[inline]
+pub unsafe fn leave(cx: NonNull<Context>) {
+    let cx = cx.as_ref();
+    cx.depth.set(cx.depth.get() - 1);
+}
+
+/// Same as `get`, but returns `None` if thread state has been destroyed.
+#[inline]
+pub fn try_get<I: ThreadIdentity>() -> Option<usize> {
+    I::try_with_current(|state| state.id)
+}
+
+pub unsafe fn push<I, T>(tr: ThreadsRef, ptr: NonNull<Slot<T>>, mail: NonNull<Mutex<Mail<T>>>) -> ThreadHandle
+where
+    I: ThreadIdentity,
+    T: Send + 'static
+{
+    I::with_current(|state| {
+        let dtor = Dtor::new(ptr, state.id, mail);
+
+        #[cfg(feature = "stats")]
+        tracing::trace!(thread = state.id, serial = state.serial, "register thread-local value");
+
+        state.list.dtors.lock()
             .unwrap()
             .insert(tr, dtor);
-        ThreadHandle(Arc::clone(&state.list))
+        ThreadHandle {
+            list: Arc::clone(&state.list),
+            serial: state.serial
+        }
     })
 }
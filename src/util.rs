@@ -1,98 +1,307 @@
-use std::{ ops, mem, alloc, slice };
-use std::marker::PhantomData;
-use std::ptr::{ self, NonNull };
+use core::{ ops, mem };
+use alloc::alloc::{ Layout, handle_alloc_error };
+use core::marker::PhantomData;
+use core::ptr::{ self, NonNull };
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use crate::loom::sync::atomic::{ AtomicPtr, Ordering };
+use crate::allocator::{ Allocator, Global };
 
 
-pub struct BoxTail<T, S>(NonNull<Inner<T, S>>);
+/// Value followed by an array, elements are aligned to at least `align` at runtime.
+pub struct BoxTail<T, S, A: Allocator = Global> {
+    ptr: NonNull<Inner<T, S>>,
+    alloc: A
+}
 
 struct Inner<T, S> {
     value: T,
     arr_len: usize,
-    _arr: IncompleteArrayField<S>
+    stride: usize,
+    offset: usize,
+    layout: Layout,
+    _marker: PhantomData<S>
+}
+
+pub enum AllocError {
+    CapacityOverflow,
+    Failed(Layout)
 }
 
-// Guaranteed alignment
-struct IncompleteArrayField<T>(PhantomData<T>, [T; 0]);
+/// Array whose elements are `stride` bytes apart.
+pub struct Array<'a, S> {
+    ptr: NonNull<u8>,
+    len: usize,
+    stride: usize,
+    _marker: PhantomData<&'a S>
+}
+
+/// Spin backoff, yields to the scheduler when model checking.
+#[cfg_attr(any(feature = "loom", feature = "shuttle"), allow(dead_code))]
+pub struct Backoff(crossbeam_utils::Backoff);
 
-impl<T, S> BoxTail<T, S> {
-    pub fn new(
+/// Lock-free multi-producer stack, values can only be taken all at once.
+pub struct AtomicStack<T> {
+    head: AtomicPtr<Node<T>>
+}
+
+struct Node<T> {
+    value: T,
+    next: *mut Node<T>
+}
+
+impl<T, S, A: Allocator> BoxTail<T, S, A> {
+    // used by `PerCpu`, which needs `std`.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn new_in(
         value: T,
         arr_len: usize,
-        arr_init: fn(*mut S)
+        align: usize,
+        arr_init: fn(*mut S),
+        alloc: A
     ) -> Self {
+        match BoxTail::try_new_in(value, arr_len, align, arr_init, alloc) {
+            Ok(boxed) => boxed,
+            Err(AllocError::CapacityOverflow) => panic!("capacity overflow"),
+            Err(AllocError::Failed(layout)) => handle_alloc_error(layout)
+        }
+    }
+
+    pub fn try_new_in(
+        value: T,
+        arr_len: usize,
+        align: usize,
+        arr_init: fn(*mut S),
+        alloc: A
+    ) -> Result<Self, AllocError> {
         // dont handle drop, because we do not need
         assert!(!mem::needs_drop::<S>());
 
-        let layout = alloc::Layout::new::<Inner<T, S>>();
-        let array_layout = alloc::Layout::array::<S>(arr_len).unwrap();
-        let (layout, _offset) = layout.extend(array_layout).unwrap();
+        let (layout, stride, offset) = Self::layout(arr_len, align)?;
 
         unsafe {
-            let ptr = NonNull::new(alloc::alloc(layout).cast::<Inner<T, S>>()).unwrap();
+            let ptr = alloc.allocate(layout)
+                .map_err(|_| AllocError::Failed(layout))?
+                .cast::<Inner<T, S>>();
 
             ptr.as_ptr().write(Inner {
-                value, arr_len,
-                _arr: IncompleteArrayField(PhantomData, [])
+                value, arr_len, stride, offset, layout,
+                _marker: PhantomData
             });
 
-            // dont use `IncompleteArrayField` to take pointer because miri is not happy
-            let arr_ptr = ptr.as_ptr().add(1).cast::<S>();
+            let arr_ptr = ptr.as_ptr().cast::<u8>().add(offset);
 
             for idx in 0..arr_len {
-                let elem = arr_ptr.add(idx);
+                let elem = arr_ptr.add(idx * stride).cast::<S>();
                 arr_init(elem);
             }
 
-            BoxTail(ptr)
+            Ok(BoxTail { ptr, alloc })
+        }
+    }
+
+    /// Layout of value followed by `arr_len` elements, with stride and offset of array.
+    pub fn layout(arr_len: usize, align: usize) -> Result<(Layout, usize, usize), AllocError> {
+        let elem_layout = Layout::new::<S>()
+            .align_to(align)
+            .map_err(|_| AllocError::CapacityOverflow)?
+            .pad_to_align();
+        let stride = elem_layout.size();
+        let array_layout = stride.checked_mul(arr_len)
+            .and_then(|size| Layout::from_size_align(size, elem_layout.align()).ok())
+            .ok_or(AllocError::CapacityOverflow)?;
+        let layout = Layout::new::<Inner<T, S>>();
+        let (layout, offset) = layout.extend(array_layout)
+            .map_err(|_| AllocError::CapacityOverflow)?;
+
+        Ok((layout, stride, offset))
+    }
+
+    /// Bytes allocated for value and array.
+    pub fn alloc_size(&self) -> usize {
+        unsafe {
+            self.ptr.as_ref().layout.size()
         }
     }
 
     #[inline]
     pub fn array_len(&self) -> usize {
         unsafe {
-            self.0.as_ref().arr_len
+            self.ptr.as_ref().arr_len
         }
     }
 
     #[inline]
-    pub fn array(&self) -> &[S] {
+    pub fn array(&self) -> Array<'_, S> {
         unsafe {
-            let arr_len = self.0.as_ref().arr_len;
-            slice::from_raw_parts(self.0.as_ptr().add(1).cast::<S>(), arr_len)
+            let inner = self.ptr.as_ref();
+
+            Array {
+                ptr: NonNull::new_unchecked(self.ptr.as_ptr().cast::<u8>().add(inner.offset)),
+                len: inner.arr_len,
+                stride: inner.stride,
+                _marker: PhantomData
+            }
         }
     }
 }
 
-impl<T, S> ops::Deref for BoxTail<T, S> {
+impl<'a, S> Array<'a, S> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&'a S> {
+        if index < self.len {
+            Some(unsafe { self.get_unchecked(index) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub unsafe fn get_unchecked(&self, index: usize) -> &'a S {
+        &*self.as_ptr_unchecked(index)
+    }
+
+    /// Raw pointer to element, which can be used for mutation if caller has unique access.
+    #[inline]
+    pub unsafe fn as_ptr_unchecked(&self, index: usize) -> *mut S {
+        self.ptr.as_ptr().add(index * self.stride).cast::<S>()
+    }
+
+    /// Extend lifetime of array.
+    ///
+    /// # Safety
+    ///
+    /// array must be alive for `'b`.
+    #[inline]
+    pub unsafe fn unbind<'b>(self) -> Array<'b, S> {
+        Array {
+            ptr: self.ptr,
+            len: self.len,
+            stride: self.stride,
+            _marker: PhantomData
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a S> {
+        let array = *self;
+        (0..array.len).map(move |index| unsafe { array.get_unchecked(index) })
+    }
+}
+
+impl<S> Clone for Array<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for Array<'_, S> {}
+
+impl<T, S, A: Allocator> ops::Deref for BoxTail<T, S, A> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
         unsafe {
-            &self.0.as_ref().value
+            &self.ptr.as_ref().value
         }
     }
 }
 
-impl<T, S> ops::DerefMut for BoxTail<T, S> {
+impl<T, S, A: Allocator> ops::DerefMut for BoxTail<T, S, A> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe {
-            &mut self.0.as_mut().value
+            &mut self.ptr.as_mut().value
         }
     }
 }
 
-impl<T, S> Drop for BoxTail<T, S> {
+impl<T, S, A: Allocator> Drop for BoxTail<T, S, A> {
     fn drop(&mut self) {
         unsafe {
+            let layout = self.ptr.as_ref().layout;
+
             if mem::needs_drop::<T>() {
-                ptr::drop_in_place(self.0.as_ptr());
+                ptr::drop_in_place(self.ptr.as_ptr());
+            }
+
+            self.alloc.deallocate(self.ptr.cast(), layout);
+        }
+    }
+}
+
+impl Backoff {
+    #[inline]
+    pub fn new() -> Backoff {
+        Backoff(crossbeam_utils::Backoff::new())
+    }
+
+    #[inline]
+    pub fn snooze(&self) {
+        #[cfg(not(feature = "loom"))]
+        #[cfg(not(feature = "shuttle"))]
+        self.0.snooze();
+
+        #[cfg(any(feature = "loom", feature = "shuttle"))]
+        crate::loom::thread::yield_now();
+    }
+}
+
+impl<T> AtomicStack<T> {
+    pub fn new() -> AtomicStack<T> {
+        AtomicStack {
+            head: AtomicPtr::new(ptr::null_mut())
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value,
+            next: ptr::null_mut()
+        }));
+
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            unsafe {
+                (*node).next = head;
             }
 
-            let arr_len = self.0.as_ref().arr_len;
-            let layout = alloc::Layout::new::<Inner<T, S>>();
-            let array_layout = alloc::Layout::array::<S>(arr_len).unwrap();
-            let (layout, _offset) = layout.extend(array_layout).unwrap();
-            alloc::dealloc(self.0.as_ptr().cast(), layout);
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_head) => head = new_head
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+
+    /// Take all values, in push order.
+    ///
+    /// Since nodes are never popped one by one, there is no ABA problem.
+    pub fn take_all(&self) -> Vec<T> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut values = Vec::new();
+
+        while !node.is_null() {
+            let Node { value, next } = *unsafe { Box::from_raw(node) };
+            values.push(value);
+            node = next;
         }
+
+        values.reverse();
+        values
+    }
+}
+
+impl<T> Drop for AtomicStack<T> {
+    fn drop(&mut self) {
+        self.take_all();
     }
 }
@@ -0,0 +1,84 @@
+#![cfg(feature = "std")]
+#![cfg(unix)]
+#![cfg(not(feature = "fork"))]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::{ Arc, Barrier, mpsc };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::{ ThreadLocal, after_fork_child };
+
+
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_after_fork_child() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tl: Arc<ThreadLocal<Counted>> = Arc::new(ThreadLocal::new());
+    let ready = Arc::new(Barrier::new(2));
+    let (tx, rx) = mpsc::channel::<()>();
+
+    // holds a value but no lock of this crate when `fork` is called.
+    let worker = {
+        let tl = tl.clone();
+        let drops = drops.clone();
+        let ready = ready.clone();
+
+        thread::spawn(move || {
+            {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, || Counted(drops.clone()));
+            }
+
+            ready.wait();
+            let _ = rx.recv();
+        })
+    };
+
+    ready.wait();
+
+    let ok = unsafe {
+        match libc::fork() {
+            -1 => panic!("fork failed"),
+            0 => {
+                let ok = panic::catch_unwind(AssertUnwindSafe(|| {
+                    after_fork_child();
+
+                    let reaped = drops.load(Ordering::Relaxed) == 1;
+
+                    // join explicitly, scope may return before thread locals are dropped.
+                    thread::scope(|s| {
+                        s.spawn(|| {
+                            per_thread_object::stack_token!(token);
+                            tl.get_or_init(token, || Counted(drops.clone()));
+                        }).join().unwrap();
+                    });
+
+                    reaped && drops.load(Ordering::Relaxed) == 2
+                })).unwrap_or(false);
+                libc::_exit(if ok { 0 } else { 1 })
+            },
+            pid => {
+                let mut status = 0;
+                assert_eq!(pid, libc::waitpid(pid, &mut status, 0));
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+        }
+    };
+    assert!(ok);
+
+    // parent is untouched.
+    assert_eq!(0, drops.load(Ordering::Relaxed));
+
+    drop(tx);
+    worker.join().unwrap();
+    assert_eq!(1, drops.load(Ordering::Relaxed));
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "std")]
+#![cfg(feature = "allocator-api2")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::Barrier;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use allocator_api2::alloc::AllocError;
+use per_thread_object::{ ThreadLocal, Allocator, Global };
+
+
+#[derive(Default)]
+struct Counts {
+    allocs: AtomicUsize,
+    deallocs: AtomicUsize,
+    bytes: AtomicUsize
+}
+
+#[derive(Clone, Copy)]
+struct Counting<'a>(&'a Counts);
+
+unsafe impl Allocator for Counting<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocs.fetch_add(1, Ordering::Relaxed);
+        self.0.bytes.fetch_add(layout.size(), Ordering::Relaxed);
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.deallocs.fetch_add(1, Ordering::Relaxed);
+        self.0.bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        Global.deallocate(ptr, layout)
+    }
+}
+
+#[test]
+fn test_counting_allocator() {
+    let counts = Counts::default();
+
+    let mut tl: ThreadLocal<usize, Counting<'_>> = ThreadLocal::builder()
+        .threads(1)
+        .page_size(1)
+        .allocator(Counting(&counts))
+        .build();
+    assert_eq!(1, counts.allocs.load(Ordering::Relaxed));
+    assert_eq!(tl.stats().reserved_bytes, counts.bytes.load(Ordering::Relaxed));
+
+    let barrier = Barrier::new(4);
+
+    thread::scope(|s| {
+        let handles = (0..4)
+            .map(|n| {
+                let (tl, barrier) = (&tl, &barrier);
+                s.spawn(move || {
+                    per_thread_object::stack_token!(token);
+                    tl.get_or_init(token, || n);
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+
+    // the array and every fallback page
+    let stats = tl.stats();
+    assert!(stats.pages >= 2);
+    assert_eq!(1 + stats.pages, counts.allocs.load(Ordering::Relaxed));
+    assert_eq!(stats.reserved_bytes, counts.bytes.load(Ordering::Relaxed));
+
+    tl.shrink_to_fit();
+    let stats = tl.stats();
+    assert_eq!(stats.reserved_bytes, counts.bytes.load(Ordering::Relaxed));
+
+    drop(tl);
+    assert_eq!(counts.allocs.load(Ordering::Relaxed), counts.deallocs.load(Ordering::Relaxed));
+    assert_eq!(0, counts.bytes.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_try_build_alloc_failed() {
+    #[derive(Clone, Copy)]
+    struct Fail;
+
+    unsafe impl Allocator for Fail {
+        fn allocate(&self, _layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            Err(AllocError)
+        }
+
+        unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+            unreachable!()
+        }
+    }
+
+    let err = ThreadLocal::<u8>::builder()
+        .allocator(Fail)
+        .try_build()
+        .err();
+    assert_eq!(Some(per_thread_object::BuildError::AllocFailed), err);
+}
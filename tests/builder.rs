@@ -0,0 +1,87 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::Barrier;
+use per_thread_object::{ ThreadLocal, BuildError, EvictionPolicy, Padding };
+
+
+#[test]
+fn test_invalid_config() {
+    let err = ThreadLocal::<u8>::builder().threads(0).try_build().err();
+    assert_eq!(Some(BuildError::ZeroCapacity), err);
+
+    let err = ThreadLocal::<u8>::builder().page_size(0).try_build().err();
+    assert_eq!(Some(BuildError::ZeroPageSize), err);
+
+    let err = ThreadLocal::<u8>::builder().eviction(EvictionPolicy::MaxLive(0)).try_build().err();
+    assert_eq!(Some(BuildError::ZeroMaxLive), err);
+
+    let err = ThreadLocal::<u8>::builder().max_threads(0).try_build().err();
+    assert_eq!(Some(BuildError::ZeroMaxThreads), err);
+
+    let err = ThreadLocal::<u8>::builder().threads(usize::MAX).try_build().err();
+    assert_eq!(Some(BuildError::CapacityOverflow), err);
+
+    // first fallback page is too large.
+    let err = ThreadLocal::<u8>::builder().threads(1).page_size(usize::MAX / 4).try_build().err();
+    assert_eq!(Some(BuildError::CapacityOverflow), err);
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn test_alloc_failed() {
+    let err = ThreadLocal::<u8>::builder()
+        .threads(1 << 56)
+        .padding(Padding::None)
+        .try_build()
+        .err();
+    assert_eq!(Some(BuildError::AllocFailed), err);
+}
+
+#[test]
+#[should_panic]
+fn test_zero_threads() {
+    let _tl: ThreadLocal<u8> = ThreadLocal::with_threads(0);
+}
+
+#[test]
+fn test_page_size() {
+    let tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .threads(1)
+        .page_size(2)
+        .init(|| 7)
+        .build();
+    let barrier = Barrier::new(5);
+
+    thread::scope(|s| {
+        let handles = (0..5)
+            .map(|_| {
+                let (tl, barrier) = (&tl, &barrier);
+                s.spawn(move || {
+                    per_thread_object::stack_token!(token);
+
+                    assert_eq!(7, *tl.get_or_create(token));
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+
+    // at least 4 threads use fallback pages of 2 and 4 slots
+    assert!(tl.stats().pages >= 2);
+}
+
+#[test]
+#[should_panic]
+fn test_create_without_init() {
+    let tl: ThreadLocal<u8> = ThreadLocal::new();
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_create(token);
+}
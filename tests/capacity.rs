@@ -0,0 +1,112 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::Barrier;
+use per_thread_object::{ ThreadLocal, InitError };
+
+
+#[test]
+fn test_max_threads() {
+    let tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .threads(1)
+        .page_size(1)
+        .max_threads(2)
+        .build();
+    let barrier = Barrier::new(2);
+
+    per_thread_object::stack_token!(token);
+    assert_eq!(Ok(&0), tl.try_get_or_init_capped::<_, ()>(token, || Ok(0)));
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            tl.get_or_init(token, || 1);
+            barrier.wait();
+            barrier.wait();
+        });
+
+        barrier.wait();
+
+        let pages = tl.stats().pages;
+
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            let ret = tl.try_get_or_init_capped::<_, ()>(token, || Ok(2));
+            assert_eq!(Err(InitError::Capacity), ret);
+            assert!(tl.get(token).is_none());
+        })
+            .join()
+            .unwrap();
+
+        assert_eq!(pages, tl.stats().pages);
+        assert_eq!(2, tl.stats().threads);
+
+        barrier.wait();
+    });
+
+    // existing value is still available at cap.
+    assert_eq!(&0, tl.get_or_init(token, || 3));
+}
+
+#[test]
+fn test_max_threads_reclaim() {
+    let tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .max_threads(1)
+        .build();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            tl.get_or_init(token, || 0);
+        })
+            .join()
+            .unwrap();
+    });
+
+    // value of exited thread no longer counts.
+    per_thread_object::stack_token!(token);
+    assert_eq!(Ok(&1), tl.try_get_or_init_capped::<_, ()>(token, || Ok(1)));
+}
+
+#[test]
+#[should_panic]
+fn test_max_threads_panic() {
+    let tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .max_threads(1)
+        .build();
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || 0);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            tl.get_or_init(token, || 1);
+        });
+    });
+}
+
+#[test]
+fn test_overflow_value() {
+    let tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .max_threads(1)
+        .overflow(usize::MAX)
+        .build();
+
+    per_thread_object::stack_token!(token);
+    assert_eq!(&0, tl.get_or_init(token, || 0));
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            assert_eq!(&usize::MAX, tl.get_or_init(token, || 1));
+            assert!(tl.get(token).is_none());
+        })
+            .join()
+            .unwrap();
+    });
+
+    assert_eq!(vec![0], tl.into_iter().collect::<Vec<_>>());
+}
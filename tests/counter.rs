@@ -0,0 +1,68 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::{ Arc, Barrier };
+use per_thread_object::{ PerThreadCounter, PerThreadGauge };
+
+
+#[test]
+fn test_counter_sum() {
+    let counter = Arc::new(PerThreadCounter::new());
+
+    let handles = (0..33)
+        .map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    counter.inc();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    counter.add(1);
+    assert_eq!(3301, counter.sum());
+}
+
+#[test]
+fn test_counter_keep_exited() {
+    let counter = PerThreadCounter::with_threads(2);
+    let bar = Barrier::new(5);
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                counter.add(10);
+                bar.wait();
+
+                // all threads are still alive
+                assert_eq!(40, counter.sum());
+                bar.wait();
+            });
+        }
+
+        bar.wait();
+        bar.wait();
+    });
+
+    assert_eq!(40, counter.sum());
+}
+
+#[test]
+fn test_gauge() {
+    let gauge = PerThreadGauge::new();
+
+    gauge.add(5);
+
+    thread::scope(|s| {
+        s.spawn(|| gauge.sub(8));
+    });
+
+    assert_eq!(-3, gauge.sum());
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::epoch::Domain;
+
+
+#[test]
+fn test_defer_after_unpin() {
+    let domain = Domain::new();
+    let count = Arc::new(AtomicUsize::new(0));
+
+    per_thread_object::stack_token!(token);
+
+    {
+        let guard = domain.pin(token);
+        let count = count.clone();
+        guard.defer(move || {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    for _ in 0..3 {
+        domain.pin(token).flush();
+    }
+
+    assert_eq!(1, count.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_pinned_thread_blocks() {
+    let domain = Domain::new();
+    let count = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (tx2, rx2) = std::sync::mpsc::channel::<()>();
+
+    thread::scope(|s| {
+        let domain = &domain;
+        s.spawn(move || {
+            per_thread_object::stack_token!(token);
+
+            let _guard = domain.pin(token);
+            tx.send(()).unwrap();
+            rx2.recv().unwrap();
+        });
+
+        rx.recv().unwrap();
+
+        per_thread_object::stack_token!(token);
+
+        {
+            let guard = domain.pin(token);
+            let count = count.clone();
+            guard.defer(move || {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+
+        for _ in 0..3 {
+            domain.pin(token).flush();
+        }
+
+        // other thread is still pinned in old epoch
+        assert_eq!(0, count.load(Ordering::Relaxed));
+
+        tx2.send(()).unwrap();
+    });
+
+    per_thread_object::stack_token!(token);
+
+    for _ in 0..3 {
+        domain.pin(token).flush();
+    }
+
+    assert_eq!(1, count.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_exited_thread_garbage() {
+    let domain = Domain::new();
+    let count = Arc::new(AtomicUsize::new(0));
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+
+            let guard = domain.pin(token);
+            let count = count.clone();
+            guard.defer(move || {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        }).join().unwrap();
+    });
+
+    per_thread_object::stack_token!(token);
+
+    for _ in 0..3 {
+        domain.pin(token).flush();
+    }
+
+    assert_eq!(1, count.load(Ordering::Relaxed));
+
+    let guard = domain.pin(token);
+    let count2 = count.clone();
+    guard.defer(move || {
+        count2.fetch_add(1, Ordering::Relaxed);
+    });
+    drop(guard);
+    drop(domain);
+
+    assert_eq!(2, count.load(Ordering::Relaxed));
+}
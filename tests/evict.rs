@@ -0,0 +1,147 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+use per_thread_object::{ ThreadLocal, EvictionPolicy };
+
+
+struct Tracked {
+    owner: thread::ThreadId,
+    dropped: Arc<Mutex<Vec<thread::ThreadId>>>
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        assert_eq!(self.owner, thread::current().id());
+        self.dropped.lock().unwrap().push(self.owner);
+    }
+}
+
+#[test]
+fn test_ttl_evict_idle() {
+    let tl: ThreadLocal<Tracked> = ThreadLocal::with_eviction(4, EvictionPolicy::Ttl(Duration::ZERO));
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let init = || Tracked { owner: thread::current().id(), dropped: dropped.clone() };
+
+    {
+        per_thread_object::stack_token!(token);
+        tl.get_or_init(token, init);
+
+        // references may still be alive, so nothing is dropped here.
+        tl.evict_idle();
+        assert!(tl.get(token).is_some());
+    }
+
+    assert!(dropped.lock().unwrap().is_empty());
+
+    {
+        per_thread_object::stack_token!(token);
+        assert!(tl.get(token).is_none());
+        assert_eq!(1, dropped.lock().unwrap().len());
+
+        tl.get_or_init(token, init);
+    }
+
+    {
+        per_thread_object::stack_token!(token);
+        assert!(tl.get(token).is_some());
+    }
+}
+
+#[test]
+fn test_ttl_keep_recent() {
+    let tl: ThreadLocal<usize> = ThreadLocal::with_eviction(4, EvictionPolicy::Ttl(Duration::from_secs(3600)));
+
+    {
+        per_thread_object::stack_token!(token);
+        tl.get_or_init(token, || 1);
+    }
+
+    tl.evict_idle();
+
+    per_thread_object::stack_token!(token);
+    assert_eq!(Some(&1), tl.get(token));
+}
+
+#[test]
+fn test_max_live_lru() {
+    let tl: ThreadLocal<Tracked> = ThreadLocal::with_eviction(4, EvictionPolicy::MaxLive(1));
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (tx2, rx2) = std::sync::mpsc::channel::<()>();
+
+    thread::scope(|s| {
+        let (tl, dropped) = (&tl, &dropped);
+
+        let handle = s.spawn(move || {
+            {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, || Tracked { owner: thread::current().id(), dropped: dropped.clone() });
+            }
+
+            tx.send(thread::current().id()).unwrap();
+            rx2.recv().unwrap();
+
+            // touching the crate processes the eviction request
+            per_thread_object::stack_token!(token);
+            tl.get(token).is_none()
+        });
+
+        let other = rx.recv().unwrap();
+
+        {
+            per_thread_object::stack_token!(token);
+            tl.get_or_init(token, || Tracked { owner: thread::current().id(), dropped: dropped.clone() });
+        }
+
+        tx2.send(()).unwrap();
+        assert!(handle.join().unwrap());
+        assert_eq!(vec![other], *dropped.lock().unwrap());
+    });
+
+    per_thread_object::stack_token!(token);
+    assert!(tl.get(token).is_some());
+}
+
+#[test]
+fn test_no_policy() {
+    let tl: ThreadLocal<usize> = ThreadLocal::new();
+
+    {
+        per_thread_object::stack_token!(token);
+        tl.get_or_init(token, || 1);
+    }
+
+    tl.evict_idle();
+
+    per_thread_object::stack_token!(token);
+    assert_eq!(Some(&1), tl.get(token));
+}
+
+#[test]
+fn test_release_current() {
+    let tl: ThreadLocal<Tracked> = ThreadLocal::new();
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let init = || Tracked { owner: thread::current().id(), dropped: dropped.clone() };
+
+    tl.with_or_init(init, |_| ());
+    tl.release_current();
+    assert_eq!(1, dropped.lock().unwrap().len());
+    assert!(tl.with(|val| val.is_none()));
+
+    {
+        per_thread_object::stack_token!(token);
+        tl.get_or_init(token, init);
+
+        // reference may still be alive
+        tl.release_current();
+        assert!(tl.get(token).is_some());
+        assert_eq!(1, dropped.lock().unwrap().len());
+    }
+
+    assert!(tl.with(|val| val.is_none()));
+    assert_eq!(2, dropped.lock().unwrap().len());
+}
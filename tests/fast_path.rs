@@ -0,0 +1,59 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+#![cfg(not(debug_assertions))]
+
+//! Regression gate of `benches/tls.rs`, run with `cargo test --release --test fast_path`.
+
+use std::hint::black_box;
+use std::time::{ Duration, Instant };
+use per_thread_object::ThreadLocal;
+
+
+const N: u32 = 100_000;
+const ROUNDS: usize = 50;
+
+fn best<F: FnMut()>(mut f: F) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+#[test]
+fn test_token_per_access() {
+    let tl: ThreadLocal<u64> = ThreadLocal::new();
+
+    let reuse = || {
+        per_thread_object::stack_token!(token);
+
+        for _ in 0..N {
+            black_box(*tl.get_or_init(token, || 0x42));
+        }
+    };
+    let per_access = || {
+        for _ in 0..N {
+            per_thread_object::stack_token!(token);
+            black_box(*tl.get_or_init(token, || 0x42));
+        }
+    };
+
+    // warm up
+    reuse();
+    per_access();
+
+    let reuse = best(reuse);
+    let per_access = best(per_access);
+
+    // tracking a token should cost about as much as the access itself.
+    assert!(
+        per_access < reuse * 2,
+        "token per access: {:?}, reused token: {:?}",
+        per_access / N,
+        reuse / N
+    );
+}
@@ -0,0 +1,134 @@
+#![cfg(feature = "fork")]
+#![cfg(unix)]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::time::Duration;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::{ Arc, Barrier, mpsc };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use per_thread_object::{ ThreadLocal, ThreadId, PerCpu, OrphanPolicy, set_orphan_policy };
+
+
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Fork, run `f` in the child and return whether it succeeded.
+fn in_child<F: FnOnce() -> bool>(f: F) -> bool {
+    unsafe {
+        match libc::fork() {
+            -1 => panic!("fork failed"),
+            0 => {
+                let ok = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(false);
+                libc::_exit(if ok { 0 } else { 1 })
+            },
+            pid => {
+                let mut status = 0;
+                assert_eq!(pid, libc::waitpid(pid, &mut status, 0));
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fork() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tl: Arc<ThreadLocal<Counted>> = Arc::new(ThreadLocal::new());
+    let ready = Arc::new(Barrier::new(2));
+    let (tx, rx) = mpsc::channel::<()>();
+
+    // keeps its value alive in parent, but does not exist in child.
+    let worker = {
+        let tl = tl.clone();
+        let drops = drops.clone();
+        let ready = ready.clone();
+
+        thread::spawn(move || {
+            {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, || Counted(drops.clone()));
+            }
+
+            ready.wait();
+            let _ = rx.recv();
+        })
+    };
+
+    ready.wait();
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || Counted(drops.clone()));
+
+    set_orphan_policy(OrphanPolicy::Drop);
+    assert!(in_child(|| {
+        let reaped = drops.load(Ordering::Relaxed) == 1;
+
+        // the id of worker can be reused.
+        // join explicitly, scope may return before thread locals are dropped.
+        thread::scope(|s| {
+            s.spawn(|| {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, || Counted(drops.clone()));
+            }).join().unwrap();
+        });
+
+        reaped
+            && drops.load(Ordering::Relaxed) == 2
+            && tl.get(token).is_some()
+    }));
+
+    set_orphan_policy(OrphanPolicy::Leak);
+    assert!(in_child(|| {
+        drops.load(Ordering::Relaxed) == 0 && tl.get(token).is_some()
+    }));
+
+    // parent is untouched.
+    assert_eq!(0, drops.load(Ordering::Relaxed));
+
+    drop(tx);
+    worker.join().unwrap();
+    assert_eq!(1, drops.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_fork_waits_for_callback() {
+    let pc: PerCpu<u32> = PerCpu::with_slots(1);
+    let forked = AtomicBool::new(false);
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+
+    // registers the fork handlers.
+    let _ = ThreadId::current();
+
+    let pc = &pc;
+
+    thread::scope(|s| {
+        s.spawn(move || pc.with(|| 0, |n| {
+            entered_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+            *n += 1;
+        }));
+
+        entered_rx.recv().unwrap();
+
+        let h = s.spawn(|| {
+            let ok = in_child(|| pc.with(|| 0, |n| *n) == 1);
+            forked.store(true, Ordering::Relaxed);
+            ok
+        });
+
+        // the slot is locked while the closure runs, so `fork` waits for it.
+        thread::sleep(Duration::from_millis(50));
+        assert!(!forked.load(Ordering::Relaxed));
+
+        release_tx.send(()).unwrap();
+        assert!(h.join().unwrap());
+    });
+}
@@ -0,0 +1,177 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::ptr;
+use std::thread;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::{ ThreadLocal, ThreadIdentity, ThreadId, Context, Global, set_current_context };
+
+
+thread_local!{
+    static CURRENT: Cell<*const Context> = const { Cell::new(ptr::null()) };
+}
+
+/// Identity of the fiber that is running on current OS thread.
+struct Fiber;
+
+unsafe impl ThreadIdentity for Fiber {
+    fn with_current<F, R>(f: F) -> R
+    where
+        F: FnOnce(&Context) -> R
+    {
+        CURRENT.with(|cur| {
+            let cx = cur.get();
+            assert!(!cx.is_null(), "no fiber is running");
+            f(unsafe { &*cx })
+        })
+    }
+}
+
+/// Stand-in for scheduler, runs one step of fiber on current OS thread.
+fn resume<R>(cx: &Context, f: impl FnOnce() -> R) -> R {
+    let prev = CURRENT.with(|cur| cur.replace(cx));
+    let ret = f();
+    CURRENT.with(|cur| cur.set(prev));
+    ret
+}
+
+/// Run one step of fiber on a new OS thread, as if it migrated.
+fn resume_elsewhere<R: Send>(cx: Context, f: impl FnOnce() -> R + Send) -> (Context, R) {
+    thread::scope(|s| {
+        s.spawn(move || {
+            let ret = resume(&cx, f);
+            (cx, ret)
+        })
+            .join()
+            .unwrap()
+    })
+}
+
+struct Tracked(Arc<AtomicUsize>);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_fiber_values_follow_context() {
+    let tl: ThreadLocal<Cell<usize>, Global, Fiber> = ThreadLocal::builder()
+        .identity::<Fiber>()
+        .build();
+    let mut fibers = (0..3).map(|_| Context::new()).collect::<Vec<_>>();
+
+    for _ in 0..3 {
+        fibers = fibers.into_iter()
+            .map(|cx| {
+                let (cx, ()) = resume_elsewhere(cx, || {
+                    per_thread_object::stack_token!(token);
+                    let val = tl.get_or_init(token, || Cell::new(0));
+                    val.set(val.get() + 1);
+                });
+                cx
+            })
+            .collect();
+    }
+
+    for cx in &fibers {
+        let val = resume(cx, || {
+            per_thread_object::stack_token!(token);
+            tl.get(token).map(Cell::get)
+        });
+        assert_eq!(Some(3), val);
+    }
+
+    // fibers share OS thread, but not values.
+    let ids = fibers.iter()
+        .map(|cx| cx.thread_id())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(3, ids.len());
+    assert_eq!(3, tl.stats().threads);
+}
+
+#[test]
+fn test_fiber_exit_drops_value() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tl: ThreadLocal<Tracked, Global, Fiber> = ThreadLocal::builder()
+        .identity::<Fiber>()
+        .build();
+
+    let a = Context::new();
+    let b = Context::new();
+
+    for cx in [&a, &b] {
+        resume(cx, || {
+            per_thread_object::stack_token!(token);
+            tl.get_or_init(token, || Tracked(drops.clone()));
+        });
+    }
+
+    drop(a);
+    assert_eq!(1, drops.load(Ordering::Relaxed));
+    assert_eq!(1, tl.stats().threads);
+
+    resume(&b, || {
+        per_thread_object::stack_token!(token);
+        assert!(tl.get(token).is_some());
+    });
+
+    drop(tl);
+    assert_eq!(2, drops.load(Ordering::Relaxed));
+    drop(b);
+    assert_eq!(2, drops.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_fiber_release_on_quiesce() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tl: ThreadLocal<Tracked, Global, Fiber> = ThreadLocal::builder()
+        .identity::<Fiber>()
+        .build();
+    let cx = Context::new();
+
+    resume(&cx, || {
+        per_thread_object::stack_token!(token);
+        tl.get_or_init(token, || Tracked(drops.clone()));
+
+        // a reference may be alive on fiber stack, so it is deferred.
+        tl.release_current();
+    });
+    assert_eq!(0, drops.load(Ordering::Relaxed));
+
+    unsafe {
+        cx.quiesce();
+    }
+    assert_eq!(1, drops.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_registered_context_ignored_with_std() {
+    fn current() -> *const Context {
+        CURRENT.with(Cell::get)
+    }
+
+    // runtimes written for `no_std` still build, but OS threads keep their own context.
+    unsafe {
+        set_current_context(current);
+    }
+
+    let tl: ThreadLocal<u32> = ThreadLocal::new();
+    let cx = Context::new_thread();
+
+    let value = resume(&cx, || {
+        assert_ne!(cx.thread_id(), ThreadId::current());
+
+        per_thread_object::stack_token!(token);
+        *tl.get_or_init(token, || 1)
+    });
+    assert_eq!(1, value);
+    drop(cx);
+
+    per_thread_object::stack_token!(token);
+    assert_eq!(Some(&1), tl.get(token));
+}
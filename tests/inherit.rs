@@ -0,0 +1,116 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use per_thread_object::inherit::{ self, InheritableThreadLocal, Inherited };
+
+
+fn leak<T: Send + 'static>(inherit: fn(&T) -> T) -> &'static InheritableThreadLocal<T> {
+    Box::leak(Box::new(InheritableThreadLocal::new(inherit)))
+}
+
+#[test]
+fn test_inherit_chain() {
+    let depth = leak(|parent: &usize| parent + 1);
+
+    per_thread_object::stack_token!(token);
+    depth.get_or_init(token, || 0);
+
+    let val = inherit::spawn(move || {
+        inherit::spawn(move || {
+            per_thread_object::stack_token!(token);
+            *depth.get(token).unwrap()
+        })
+            .join()
+            .unwrap()
+    })
+        .join()
+        .unwrap();
+
+    assert_eq!(2, val);
+    assert_eq!(0, *depth.get(token).unwrap());
+}
+
+#[test]
+fn test_inherit_only_when_set() {
+    let tl = leak(|parent: &u32| *parent);
+
+    let val = inherit::spawn(move || {
+        per_thread_object::stack_token!(token);
+        tl.get(token).copied()
+    })
+        .join()
+        .unwrap();
+    assert_eq!(None, val);
+
+    // std spawn does not inherit
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || 1);
+    let val = thread::spawn(move || {
+        per_thread_object::stack_token!(token);
+        tl.get(token).copied()
+    })
+        .join()
+        .unwrap();
+    assert_eq!(None, val);
+}
+
+#[test]
+fn test_inherit_capture_install() {
+    let tl = leak(|parent: &String| parent.clone());
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || String::from("ctx"));
+
+    let inherited = Inherited::capture();
+
+    let val = thread::Builder::new()
+        .name("child".into())
+        .spawn(move || {
+            per_thread_object::stack_token!(token);
+
+            // existing value is kept
+            tl.get_or_init(token, || String::from("own"));
+            inherited.install();
+            tl.get(token).cloned()
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    assert_eq!(Some(String::from("own")), val);
+
+    let val = inherit::Builder::new()
+        .name("child".into())
+        .spawn(move || {
+            per_thread_object::stack_token!(token);
+            tl.get(token).cloned()
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    assert_eq!(Some(String::from("ctx")), val);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_inherit_rayon() {
+    let tl = leak(|parent: &u32| parent * 2);
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || 21);
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(2)
+        .spawn_handler(inherit::rayon_spawn_handler)
+        .build()
+        .unwrap();
+
+    let vals = pool.broadcast(|_| {
+        per_thread_object::stack_token!(token);
+        tl.get(token).copied()
+    });
+    assert_eq!(vec![Some(42), Some(42)], vals);
+}
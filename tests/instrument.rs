@@ -0,0 +1,84 @@
+#![cfg(feature = "std")]
+#![cfg(feature = "stats")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::fmt;
+use std::thread;
+use std::sync::Mutex;
+use tracing::{ Event, Metadata, Subscriber };
+use tracing::field::{ Field, Visit };
+use tracing::span::{ Attributes, Id, Record };
+use per_thread_object::ThreadLocal;
+
+
+static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Collector;
+
+struct Message(Option<String>);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+impl Subscriber for Collector {
+    fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+    fn new_span(&self, _: &Attributes<'_>) -> Id { Id::from_u64(1) }
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn enter(&self, _: &Id) {}
+    fn exit(&self, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut msg = Message(None);
+        event.record(&mut msg);
+        MESSAGES.lock().unwrap().extend(msg.0);
+    }
+}
+
+fn count(msg: &str) -> usize {
+    MESSAGES.lock().unwrap()
+        .iter()
+        .filter(|m| *m == msg)
+        .count()
+}
+
+#[test]
+fn test_instrument() {
+    tracing::subscriber::set_global_default(Collector).unwrap();
+
+    let tl: ThreadLocal<u64> = ThreadLocal::with_threads(1);
+
+    thread::scope(|s| {
+        for _ in 0..3 {
+            s.spawn(|| {
+                per_thread_object::stack_token!(token);
+
+                tl.get_or_init(token, || 0);
+                tl.get_or_init(token, || 0);
+                tl.get(token);
+            }).join().unwrap();
+        }
+    });
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || 0);
+
+    let stats = tl.stats();
+    assert_eq!(4, stats.inits);
+    assert_eq!(3, stats.exit_drops);
+    assert_eq!(0, stats.releases);
+    // first `get_or_init` of each thread is an initialization
+    assert_eq!(6, stats.fast_hits + stats.fallback_hits);
+
+    assert!(count("register thread-local value") >= 4);
+    assert!(count("thread exit") >= 3);
+
+    drop(tl);
+    assert_eq!(1, count("thread-local storage dropped"));
+}
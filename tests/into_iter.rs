@@ -0,0 +1,29 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use per_thread_object::ThreadLocal;
+
+
+#[test]
+fn test_into_iter() {
+    let tl: ThreadLocal<usize> = ThreadLocal::with_threads(2);
+
+    thread::scope(|s| {
+        for i in 0..4 {
+            let tl = &tl;
+            s.spawn(move || {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, || i);
+            }).join().unwrap();
+        }
+    });
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || 4);
+
+    // values of exited threads are already dropped
+    let values = tl.into_iter().collect::<Vec<_>>();
+    assert_eq!(vec![4], values);
+}
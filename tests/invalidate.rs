@@ -0,0 +1,138 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::{ Arc, Mutex };
+use per_thread_object::ThreadLocal;
+
+
+struct Value {
+    version: usize,
+    drops: Arc<Mutex<Vec<(usize, thread::ThreadId)>>>
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        self.drops.lock().unwrap().push((self.version, thread::current().id()));
+    }
+}
+
+#[test]
+fn test_invalidate_rebuild() {
+    let tl: ThreadLocal<Value> = ThreadLocal::with_threads(1);
+    let drops = Arc::new(Mutex::new(Vec::new()));
+    let version = Mutex::new(0);
+
+    let init = || Value {
+        version: *version.lock().unwrap(),
+        drops: drops.clone()
+    };
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            let get = || {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, init).version
+            };
+
+            assert_eq!(0, get());
+
+            *version.lock().unwrap() = 1;
+            tl.invalidate_all();
+
+            // no reference of this token has observed the old value.
+            assert_eq!(1, get());
+
+            *version.lock().unwrap() = 2;
+
+            {
+                per_thread_object::stack_token!(token);
+
+                let old = tl.get_or_init(token, init);
+                assert_eq!(1, old.version);
+
+                tl.invalidate_all();
+
+                // old reference is still alive, so it will not be rebuilt.
+                assert_eq!(1, tl.get_or_init(token, init).version);
+                assert_eq!(1, old.version);
+            }
+
+            assert_eq!(2, get());
+            assert_eq!(2, get());
+
+            let id = thread::current().id();
+            let drops = drops.lock().unwrap();
+            assert_eq!(&[(0, id), (1, id)], &drops[..]);
+        }).join().unwrap();
+    });
+
+    // thread exit
+    assert_eq!(3, drops.lock().unwrap().len());
+}
+
+#[test]
+fn test_invalidate_nested_token() {
+    let tl: ThreadLocal<usize> = ThreadLocal::new();
+
+    per_thread_object::stack_token!(token);
+
+    let val = tl.get_or_init(token, || 1);
+    tl.invalidate_all();
+
+    {
+        // inner token does not start a new session
+        per_thread_object::stack_token!(token);
+        assert_eq!(1, *tl.get_or_init(token, || 2));
+    }
+
+    {
+        per_thread_object::stack_token!(token);
+        assert_eq!(1, *tl.get_or_init(token, || 2));
+    }
+
+    assert_eq!(1, *val);
+}
+
+#[test]
+fn test_invalidate_unused_outer_token() {
+    let tl: ThreadLocal<usize> = ThreadLocal::new();
+
+    // never accesses a value, so it holds no reference.
+    per_thread_object::stack_token!(outer);
+    let _ = outer;
+
+    {
+        per_thread_object::stack_token!(token);
+        assert_eq!(1, *tl.get_or_init(token, || 1));
+    }
+
+    tl.invalidate_all();
+
+    {
+        per_thread_object::stack_token!(token);
+        assert_eq!(2, *tl.get_or_init(token, || 2));
+    }
+}
+
+#[test]
+fn test_invalidate_during_init() {
+    let tl: ThreadLocal<usize> = ThreadLocal::new();
+
+    {
+        per_thread_object::stack_token!(token);
+
+        // built before invalidation, so it is stale once stored.
+        assert_eq!(1, *tl.get_or_init(token, || {
+            tl.invalidate_all();
+            1
+        }));
+        assert_eq!(1, *tl.get_or_init(token, || 2));
+    }
+
+    {
+        per_thread_object::stack_token!(token);
+        assert_eq!(2, *tl.get_or_init(token, || 2));
+    }
+}
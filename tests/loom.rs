@@ -0,0 +1,141 @@
+#![cfg(feature = "loom")]
+
+use loom::thread;
+use loom::sync::Arc;
+use loom::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::{ ThreadLocal, InitError };
+
+
+/// Explore schedules with at most three preemptions,
+/// more can be set with `LOOM_MAX_PREEMPTIONS`.
+fn model<F>(f: F)
+where
+    F: Fn() + Sync + Send + 'static
+{
+    let mut builder = loom::model::Builder::new();
+
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(3);
+    }
+
+    builder.check(f);
+}
+
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Error;
+
+#[test]
+fn test_loom_get_or_try_init() {
+    model(|| {
+        // one slot in the array, the other thread goes to the fallback page.
+        let tl: Arc<ThreadLocal<usize>> = Arc::new(ThreadLocal::with_threads(1));
+        let tl2 = tl.clone();
+
+        let j = thread::spawn(move || {
+            per_thread_object::stack_token!(token);
+
+            assert_eq!(Err(InitError::Init(Error)), tl2.try_get_or_init_capped(token, || Err(Error)));
+            assert!(tl2.get(token).is_none());
+
+            let val = *tl2.try_get_or_init_capped(token, || Ok::<_, Error>(2)).unwrap();
+            assert_eq!(2, val);
+        });
+
+        per_thread_object::stack_token!(token);
+
+        let val = *tl.try_get_or_init_capped(token, || Ok::<_, Error>(1)).unwrap();
+        assert_eq!(1, val);
+
+        j.join().unwrap();
+
+        assert_eq!(Some(&1), tl.get(token));
+    });
+}
+
+#[test]
+fn test_loom_thread_exit() {
+    model(|| {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let tl: Arc<ThreadLocal<Counted>> = Arc::new(ThreadLocal::builder()
+            .threads(1)
+            .shrink_on_exit(true)
+            .build());
+
+        let handles = (0..2)
+            .map(|_| {
+                let tl = tl.clone();
+                let drops = drops.clone();
+
+                thread::spawn(move || {
+                    per_thread_object::stack_token!(token);
+                    tl.get_or_init(token, || Counted(drops.clone()));
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        // loom returns from `join` before thread locals are dropped,
+        // so wait until both threads returned their ids, which is the last step of exit.
+        // the second thread reuses the id of the first if it exited already.
+        loop {
+            let ids = per_thread_object::thread_stats();
+
+            if ids.free == ids.max {
+                assert!(matches!(ids.max, 1 | 2));
+                break
+            }
+
+            thread::yield_now();
+        }
+
+        let stats = tl.stats();
+        assert_eq!(2, drops.load(Ordering::Relaxed));
+        assert_eq!(0, stats.threads);
+        assert_eq!(0, stats.fast_slots + stats.fallback_slots);
+
+        // the fallback page of the second thread, if it needed one, is freed at its exit.
+        assert_eq!(0, stats.pages);
+
+        drop(tl);
+        assert_eq!(2, drops.load(Ordering::Relaxed));
+    });
+}
+
+#[test]
+fn test_loom_drop_storage() {
+    model(|| {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let tl: Arc<ThreadLocal<Counted>> = Arc::new(ThreadLocal::with_threads(1));
+        let tl2 = tl.clone();
+        let drops2 = drops.clone();
+
+        // storage is dropped by whichever finishes last,
+        // possibly while the other thread is exiting.
+        let j = thread::spawn(move || {
+            per_thread_object::stack_token!(token);
+            tl2.get_or_init(token, || Counted(drops2.clone()));
+        });
+
+        {
+            per_thread_object::stack_token!(token);
+            tl.get_or_init(token, || Counted(drops.clone()));
+        }
+
+        drop(tl);
+        j.join().unwrap();
+
+        // the value of main thread is dropped with storage.
+        assert_eq!(2, drops.load(Ordering::Relaxed));
+    });
+}
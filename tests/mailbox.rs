@@ -0,0 +1,101 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::cell::Cell;
+use std::sync::{ mpsc, Arc };
+use std::time::Duration;
+use per_thread_object::{ ThreadLocal, ThreadId, EvictionPolicy };
+
+
+#[test]
+fn test_post_other_thread() {
+    let tl: ThreadLocal<Cell<u32>> = ThreadLocal::with_threads(1);
+    let (tx, rx) = mpsc::channel();
+    let (tx2, rx2) = mpsc::channel::<()>();
+
+    thread::scope(|s| {
+        let tl = &tl;
+        let h = s.spawn(move || {
+            per_thread_object::stack_token!(token);
+
+            tl.get_or_init(token, || Cell::new(1));
+            tx.send(ThreadId::current()).unwrap();
+            rx2.recv().unwrap();
+
+            tl.get_or_init(token, || Cell::new(0)).get()
+        });
+
+        let id = rx.recv().unwrap();
+        assert!(tl.post(id, |val| val.set(val.get() + 10)).is_ok());
+        assert!(tl.post(id, |val| val.set(val.get() * 2)).is_ok());
+        tx2.send(()).unwrap();
+
+        assert_eq!(22, h.join().unwrap());
+    });
+
+    // current thread has no value
+    assert!(tl.post(ThreadId::current(), |_| ()).is_err());
+}
+
+#[test]
+fn test_undelivered() {
+    let tl: ThreadLocal<Cell<u32>> = ThreadLocal::new();
+    let pending = Arc::new(2);
+    let (tx, rx) = mpsc::channel();
+    let (tx2, rx2) = mpsc::channel::<()>();
+
+    let h = {
+        let tl = &tl;
+        thread::scope(|s| {
+            let h = s.spawn(move || {
+                per_thread_object::stack_token!(token);
+
+                tl.get_or_init(token, || Cell::new(1));
+                tx.send(ThreadId::current()).unwrap();
+                rx2.recv().unwrap();
+            });
+
+            let id = rx.recv().unwrap();
+            let captured = pending.clone();
+            assert!(tl.post(id, move |val| val.set(*captured)).is_ok());
+            tx2.send(()).unwrap();
+            h.join().unwrap();
+            id
+        })
+    };
+
+    // owner exited without draining, pending message is kept for sender.
+    assert_eq!(2, Arc::strong_count(&pending));
+    assert!(tl.post(h, |_| ()).is_err());
+
+    let msgs = tl.take_undelivered();
+    assert_eq!(1, msgs.len());
+    assert!(tl.take_undelivered().is_empty());
+
+    let val = Cell::new(0);
+    for msg in msgs {
+        msg(&val);
+    }
+    assert_eq!(2, val.get());
+    assert_eq!(1, Arc::strong_count(&pending));
+}
+
+#[test]
+fn test_undelivered_evicted() {
+    let tl: ThreadLocal<Cell<u32>> = ThreadLocal::with_eviction(4, EvictionPolicy::Ttl(Duration::ZERO));
+
+    {
+        per_thread_object::stack_token!(token);
+        tl.get_or_init(token, || Cell::new(1));
+    }
+
+    assert!(tl.post(ThreadId::current(), |val| val.set(2)).is_ok());
+    tl.evict_idle();
+
+    // evicted before the message is processed.
+    per_thread_object::stack_token!(token);
+    assert!(tl.get(token).is_none());
+    assert_eq!(1, tl.take_undelivered().len());
+}
@@ -0,0 +1,87 @@
+#![cfg(not(feature = "std"))]
+
+use std::ptr;
+use std::thread;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::{ ThreadLocal, ThreadId, Context, set_current_context };
+
+
+thread_local!{
+    static CURRENT: Cell<*const Context> = const { Cell::new(ptr::null()) };
+}
+
+fn current() -> *const Context {
+    CURRENT.try_with(Cell::get).unwrap_or(ptr::null())
+}
+
+/// Stand-in for RTOS runtime, runs `f` as a task with its own context.
+fn task<R>(f: impl FnOnce() -> R) -> R {
+    unsafe {
+        set_current_context(current);
+    }
+
+    let cx = Context::new_thread();
+    CURRENT.with(|cur| cur.set(&cx));
+    let ret = f();
+    CURRENT.with(|cur| cur.set(ptr::null()));
+    drop(cx);
+    ret
+}
+
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_no_std_thread_local() {
+    let tl: Arc<ThreadLocal<ThreadId>> = Arc::new(ThreadLocal::new());
+
+    let handles = (0..4)
+        .map(|_| {
+            let tl = tl.clone();
+            thread::spawn(move || task(|| {
+                per_thread_object::stack_token!(token);
+
+                let id = *tl.get_or_init(token, ThreadId::current);
+                assert_eq!(ThreadId::current(), id);
+                assert_eq!(Some(&id), tl.get(token));
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    task(|| {
+        per_thread_object::stack_token!(token);
+        assert!(tl.get(token).is_none());
+    });
+}
+
+#[test]
+fn test_no_std_drop_at_exit() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tl: Arc<ThreadLocal<Counted>> = Arc::new(ThreadLocal::new());
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| task(|| {
+                per_thread_object::stack_token!(token);
+                tl.get_or_init(token, || Counted(drops.clone()));
+            }));
+        }
+    });
+
+    // values are dropped with the context of their task.
+    assert_eq!(4, drops.load(Ordering::Relaxed));
+
+    let tl = Arc::try_unwrap(tl).ok().unwrap();
+    assert_eq!(0, tl.into_iter().count());
+}
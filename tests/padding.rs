@@ -0,0 +1,80 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::{ mem, thread };
+use std::sync::Barrier;
+use per_thread_object::{ ThreadLocal, Padding };
+
+
+#[test]
+fn test_padding_footprint() {
+    let padded: ThreadLocal<u8> = ThreadLocal::builder()
+        .threads(16)
+        .build();
+    let compact: ThreadLocal<u8> = ThreadLocal::builder()
+        .threads(16)
+        .padding(Padding::None)
+        .build();
+
+    assert_eq!(16, compact.stats().capacity);
+    assert!(compact.stats().reserved_bytes < padded.stats().reserved_bytes);
+}
+
+#[test]
+fn test_compact_slot_size() {
+    // storage header, which does not grow with threads.
+    const HEADER: usize = 512;
+
+    fn check<T: Send + 'static>() {
+        let footprint = |threads| ThreadLocal::<T>::builder()
+            .threads(threads)
+            .padding(Padding::None)
+            .build()
+            .stats()
+            .reserved_bytes;
+
+        // only flags and session of owner are kept beside the value,
+        // and access count with `stats` feature.
+        let hits = if cfg!(feature = "stats") { mem::size_of::<u64>() } else { 0 };
+        let slot = mem::size_of::<Option<T>>() + 2 * mem::size_of::<u32>() + hits;
+
+        assert!(footprint(16) <= 16 * slot + HEADER);
+        assert!(footprint(32) - footprint(16) <= 16 * slot);
+    }
+
+    check::<u32>();
+    check::<u64>();
+}
+
+#[test]
+fn test_padding_pages() {
+    for padding in [Padding::CacheLine, Padding::None] {
+        let tl: ThreadLocal<usize> = ThreadLocal::builder()
+            .threads(1)
+            .padding(padding)
+            .build();
+        let barrier = Barrier::new(4);
+
+        thread::scope(|s| {
+            let handles = (0..4)
+                .map(|i| {
+                    let (tl, barrier) = (&tl, &barrier);
+                    s.spawn(move || {
+                        per_thread_object::stack_token!(token);
+
+                        tl.get_or_init(token, || i);
+                        barrier.wait();
+                        assert_eq!(i, *tl.get(token).unwrap());
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+
+        assert!(tl.stats().pages >= 1);
+    }
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "std")]
+#![cfg(feature = "rayon")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::collections::HashSet;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use rayon::prelude::*;
+use per_thread_object::ThreadLocal;
+
+
+#[test]
+fn test_par_fold() {
+    let accs = ThreadLocal::par_fold(0..10_000u64, Vec::new, |acc, n| acc.push(n))
+        .collect::<Vec<_>>();
+
+    assert!(!accs.is_empty());
+    assert!(accs.len() <= rayon::current_num_threads() + 1);
+
+    let mut all = accs.into_iter().flatten().collect::<Vec<_>>();
+    all.sort_unstable();
+    assert_eq!((0..10_000).collect::<Vec<_>>(), all);
+}
+
+#[test]
+fn test_par_fold_nested() {
+    // `fold` blocks in rayon, so the worker may steal other items reentrantly.
+    let sum: u64 = ThreadLocal::par_fold(0..64u64, || 0, |acc, n| {
+        *acc += (0..n).into_par_iter().map(|_| 1).sum::<u64>();
+    })
+        .sum();
+
+    assert_eq!((0..64).sum::<u64>(), sum);
+}
+
+#[test]
+fn test_init_on_pool() {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+    let tl: ThreadLocal<usize> = ThreadLocal::new();
+    let count = AtomicUsize::new(0);
+
+    tl.init_on_pool(&pool, || count.fetch_add(1, Ordering::Relaxed));
+    assert_eq!(3, count.load(Ordering::Relaxed));
+
+    // already initialized
+    tl.init_on_pool(&pool, || count.fetch_add(1, Ordering::Relaxed));
+    assert_eq!(3, count.load(Ordering::Relaxed));
+
+    let ids = pool.broadcast(|_| {
+        per_thread_object::stack_token!(token);
+        *tl.get(token).unwrap()
+    });
+    assert_eq!(3, ids.into_iter().collect::<HashSet<_>>().len());
+
+    let mut values = tl.into_iter().collect::<Vec<_>>();
+    values.sort_unstable();
+    assert_eq!(vec![0, 1, 2], values);
+}
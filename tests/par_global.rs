@@ -0,0 +1,17 @@
+#![cfg(feature = "std")]
+#![cfg(feature = "rayon")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use per_thread_object::ThreadLocal;
+
+
+// own test binary, because the global pool can only be built once per process.
+#[test]
+fn test_new_does_not_start_global_pool() {
+    let _tl: ThreadLocal<usize> = ThreadLocal::new();
+    let _tl: ThreadLocal<usize> = ThreadLocal::builder().build();
+    let _tl: ThreadLocal<usize> = ThreadLocal::default();
+
+    assert!(rayon::ThreadPoolBuilder::new().num_threads(2).build_global().is_ok());
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::PerCpu;
+
+
+#[test]
+fn test_percpu_sum() {
+    let counter: PerCpu<u64> = PerCpu::new();
+
+    thread::scope(|s| {
+        for _ in 0..32 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    counter.with(|| 0, |n| *n += 1);
+                }
+            });
+        }
+    });
+
+    let mut sum = 0;
+    counter.for_each(|n| sum += *n);
+    assert_eq!(3200, sum);
+    assert_eq!(3200, counter.into_iter().sum::<u64>());
+}
+
+#[test]
+fn test_percpu_shared_slot() {
+    let inits = AtomicUsize::new(0);
+    let list: PerCpu<Vec<usize>> = PerCpu::with_slots(1);
+
+    thread::scope(|s| {
+        for n in 0..8 {
+            let (list, inits) = (&list, &inits);
+            s.spawn(move || {
+                list.with(
+                    || {
+                        inits.fetch_add(1, Ordering::Relaxed);
+                        Vec::new()
+                    },
+                    |list| list.push(n)
+                );
+            });
+        }
+    });
+
+    // all threads share one slot
+    assert_eq!(1, inits.load(Ordering::Relaxed));
+
+    let mut values = list.into_iter().flatten().collect::<Vec<_>>();
+    values.sort_unstable();
+    assert_eq!((0..8).collect::<Vec<_>>(), values);
+}
+
+#[test]
+#[should_panic]
+fn test_percpu_zero_slots() {
+    let _pc: PerCpu<u8> = PerCpu::with_slots(0);
+}
+
+#[test]
+fn test_percpu_panic_in_with() {
+    let counter: PerCpu<u64> = PerCpu::with_slots(1);
+
+    let ret = panic::catch_unwind(AssertUnwindSafe(|| {
+        counter.with(|| 0, |n| {
+            *n += 1;
+            panic!("boom");
+        })
+    }));
+    assert!(ret.is_err());
+
+    // slot is still usable and keeps the update made before panic.
+    assert_eq!(2, counter.with(|| 0, |n| {
+        *n += 1;
+        *n
+    }));
+
+    let mut sum = 0;
+    counter.for_each(|n| sum += *n);
+    assert_eq!(2, sum);
+}
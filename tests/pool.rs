@@ -0,0 +1,72 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::PerThreadPool;
+
+
+struct Tracked(Arc<AtomicUsize>);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_pool_reuse() {
+    let pool: PerThreadPool<Vec<u8>> = PerThreadPool::new();
+
+    let mut a = pool.checkout(Vec::new);
+    a.push(1);
+    let b = pool.checkout(|| vec![2]);
+    assert_eq!(&[2], &b[..]);
+    drop(a);
+
+    let a = pool.checkout(Vec::new);
+    assert_eq!(&[1], &a[..]);
+
+    let c = pool.checkout(Vec::new);
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_pool_remote_return() {
+    let pool: PerThreadPool<Vec<u8>> = PerThreadPool::new();
+
+    let a = pool.checkout(|| vec![1]);
+
+    thread::scope(|s| {
+        s.spawn(move || drop(a));
+    });
+
+    let a = pool.checkout(Vec::new);
+    assert_eq!(&[1], &a[..]);
+}
+
+#[test]
+fn test_pool_owner_exit() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let pool: PerThreadPool<Tracked> = PerThreadPool::new();
+
+    thread::scope(|s| {
+        let obj = s.spawn(|| {
+            let a = pool.checkout(|| Tracked(drops.clone()));
+            let b = pool.checkout(|| Tracked(drops.clone()));
+            drop(b);
+            a
+        }).join().unwrap();
+
+        // free list is released at owner exit
+        assert_eq!(1, drops.load(Ordering::Relaxed));
+
+        drop(obj);
+        assert_eq!(2, drops.load(Ordering::Relaxed));
+    });
+
+    drop(pool);
+    assert_eq!(2, drops.load(Ordering::Relaxed));
+}
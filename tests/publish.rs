@@ -0,0 +1,54 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use per_thread_object::{ PerThreadPublished, ThreadId };
+
+
+#[test]
+fn test_snapshot_consistent() {
+    let published: PerThreadPublished<[u64; 8]> = PerThreadPublished::with_threads(2);
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    published.publish([i; 8]);
+                    i += 1;
+                }
+            });
+        }
+
+        for _ in 0..1000 {
+            for (_, val) in published.snapshot_all() {
+                assert!(val.iter().all(|&n| n == val[0]));
+            }
+        }
+
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+#[test]
+fn test_snapshot_thread_id() {
+    let published = PerThreadPublished::new();
+    published.publish(1);
+
+    let id = thread::scope(|s| {
+        s.spawn(|| {
+            published.publish(2);
+            published.publish(3);
+            ThreadId::current()
+        }).join().unwrap()
+    });
+
+    assert_ne!(id, ThreadId::current());
+
+    let mut snapshot = published.snapshot_all();
+    snapshot.sort_by_key(|(_, val)| *val);
+    assert_eq!(vec![(ThreadId::current(), 1)], snapshot);
+}
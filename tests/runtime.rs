@@ -0,0 +1,66 @@
+#![cfg(feature = "std")]
+#![cfg(feature = "tokio")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::{ ThreadLocal, WorkerLocals };
+
+
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_worker_locals() {
+    let inits = Arc::new(AtomicUsize::new(0));
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tl: Arc<ThreadLocal<Counted>> = Arc::new(ThreadLocal::new());
+
+    let mut locals = WorkerLocals::new();
+    {
+        let (inits, drops) = (inits.clone(), drops.clone());
+        locals.register(tl.clone(), move || {
+            inits.fetch_add(1, Ordering::Relaxed);
+            Counted(drops.clone())
+        });
+    }
+
+    let rt = locals.install(tokio::runtime::Builder::new_multi_thread().worker_threads(2))
+        .build()
+        .unwrap();
+
+    let tl2 = tl.clone();
+    rt.block_on(async move {
+        let tasks = (0..8)
+            .map(|_| {
+                let tl = tl2.clone();
+                tokio::spawn(async move {
+                    // pre-initialized on every worker
+                    let found = tl.with(|val| val.is_some());
+                    tokio::task::yield_now().await;
+                    found
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            assert!(task.await.unwrap());
+        }
+    });
+
+    drop(rt);
+
+    let inits = inits.load(Ordering::Relaxed);
+    assert!(inits >= 2);
+    assert_eq!(inits, drops.load(Ordering::Relaxed));
+
+    // values are released by runtime, not by dropping `ThreadLocal`
+    drop(tl);
+    assert_eq!(inits, drops.load(Ordering::Relaxed));
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::mpsc;
+use std::time::Duration;
+use per_thread_object::ShardedRwLock;
+
+
+#[test]
+fn test_rwlock_readers_writers() {
+    let lock = ShardedRwLock::new((0usize, 0usize));
+
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    per_thread_object::stack_token!(token);
+
+                    let val = lock.read(token);
+                    assert_eq!(val.0, val.1);
+                }
+            });
+        }
+
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    let mut val = lock.write();
+                    val.0 += 1;
+                    val.1 += 1;
+                }
+            });
+        }
+    });
+
+    assert_eq!((400, 400), lock.into_inner());
+}
+
+#[test]
+fn test_rwlock_reentrant_read() {
+    let lock = ShardedRwLock::with_threads(vec![1], 1);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+
+            // fallback page
+            let r1 = lock.read(token);
+            let r2 = lock.read(token);
+            assert_eq!(r1[0], r2[0]);
+        });
+    });
+
+    lock.write().push(2);
+
+    per_thread_object::stack_token!(token);
+    assert_eq!(&[1, 2], &lock.read(token)[..]);
+}
+
+#[test]
+fn test_rwlock_reader_exits_while_writer_waits() {
+    let lock = ShardedRwLock::new(0);
+    let (registered_tx, registered_rx) = mpsc::channel();
+    let (exit_tx, exit_rx) = mpsc::channel::<()>();
+
+    thread::scope(|s| {
+        let lock = &lock;
+
+        per_thread_object::stack_token!(token);
+        let guard = lock.read(token);
+
+        let reader = s.spawn(move || {
+            per_thread_object::stack_token!(token);
+            drop(lock.read(token));
+            registered_tx.send(()).unwrap();
+            exit_rx.recv().unwrap();
+        });
+        registered_rx.recv().unwrap();
+
+        let writer = s.spawn(move || *lock.write() += 1);
+
+        // let writer start waiting for `guard`.
+        thread::sleep(Duration::from_millis(50));
+
+        // reader drops its slot while writer waits.
+        exit_tx.send(()).unwrap();
+        reader.join().unwrap();
+
+        drop(guard);
+        writer.join().unwrap();
+    });
+
+    assert_eq!(1, lock.into_inner());
+}
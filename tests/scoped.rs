@@ -0,0 +1,79 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::cell::RefCell;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use per_thread_object::ScopedThreadLocal;
+
+
+struct Borrowed<'a> {
+    drops: &'a AtomicUsize
+}
+
+impl Drop for Borrowed<'_> {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_scoped_borrow() {
+    let drops = AtomicUsize::new(0);
+
+    {
+        let tl = ScopedThreadLocal::with_threads(2);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    per_thread_object::stack_token!(token);
+
+                    let val = tl.get_or_init(token, || Borrowed { drops: &drops });
+                    assert!(std::ptr::eq(val.drops, &drops));
+                });
+            }
+        });
+
+        // values are not dropped at thread exit,
+        // unless its id was reused by another thread.
+        assert!(drops.load(Ordering::Relaxed) < 8);
+    }
+
+    assert_eq!(8, drops.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_scoped_reused_id() {
+    let tl: ScopedThreadLocal<'_, RefCell<usize>> = ScopedThreadLocal::new();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            *tl.get_or_init(token, Default::default).borrow_mut() += 1;
+        }).join().unwrap();
+
+        // the next thread may reuse id of previous thread,
+        // but it never observes the old value.
+        s.spawn(|| {
+            per_thread_object::stack_token!(token);
+            assert!(tl.get(token).is_none());
+            assert_eq!(0, *tl.get_or_init(token, Default::default).borrow());
+        }).join().unwrap();
+    });
+
+    per_thread_object::stack_token!(token);
+    assert!(tl.get(token).is_none());
+    assert_eq!(1, *tl.get_or_init(token, || RefCell::new(1)).borrow());
+}
+
+#[test]
+fn test_scoped_reentrant_init() {
+    let tl: ScopedThreadLocal<'_, usize> = ScopedThreadLocal::new();
+
+    per_thread_object::stack_token!(token);
+
+    let val = tl.get_or_init(token, || *tl.get_or_init(token, || 1) + 1);
+    assert_eq!(1, *val);
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::mpsc;
+use per_thread_object::{ ThreadLocal, ThreadId };
+
+
+// keep it as the only test in this file,
+// because threads of other tests would hold the pages.
+#[test]
+fn test_shrink() {
+    // take a low id before spawning.
+    let id = ThreadId::current().index();
+
+    let mut tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .threads(1)
+        .page_size(1)
+        .build();
+    spawn_burst(&tl, 8);
+    assert!(tl.stats().pages > pages_for(id));
+
+    per_thread_object::stack_token!(token);
+    tl.get_or_init(token, || 42);
+
+    tl.shrink_to_fit();
+    assert_eq!(pages_for(id), tl.stats().pages);
+    assert_eq!(Some(&42), tl.get(token));
+
+    // freed pages are allocated again on demand.
+    spawn_burst(&tl, 2);
+    tl.shrink_to_fit();
+    assert_eq!(pages_for(id), tl.stats().pages);
+
+    let tl: ThreadLocal<usize> = ThreadLocal::builder()
+        .threads(1)
+        .page_size(1)
+        .shrink_on_exit(true)
+        .build();
+    spawn_burst(&tl, 8);
+    assert_eq!(pages_for(id), tl.stats().pages);
+    assert_eq!(0, tl.stats().threads);
+}
+
+/// Number of pages of 1, 2, 4.. slots needed for `id`, with one lock-free slot.
+fn pages_for(id: usize) -> usize {
+    (usize::BITS - id.leading_zeros()) as usize
+}
+
+/// Initialize values on `num` threads alive at the same time, then stop them one by one.
+fn spawn_burst(tl: &ThreadLocal<usize>, num: usize) {
+    thread::scope(|s| {
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let threads = (0..num)
+            .map(|n| {
+                let ready_tx = ready_tx.clone();
+                let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+                let handle = s.spawn(move || {
+                    per_thread_object::stack_token!(token);
+                    tl.get_or_init(token, || n);
+                    ready_tx.send(()).unwrap();
+                    let _ = stop_rx.recv();
+                });
+
+                (stop_tx, handle)
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..num {
+            ready_rx.recv().unwrap();
+        }
+
+        for (stop_tx, handle) in threads {
+            drop(stop_tx);
+            handle.join().unwrap();
+        }
+    });
+}
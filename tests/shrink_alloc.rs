@@ -0,0 +1,80 @@
+#![cfg(feature = "std")]
+#![cfg(feature = "allocator-api2")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::mpsc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use allocator_api2::alloc::AllocError;
+use per_thread_object::{ ThreadLocal, ThreadId, Allocator, Global };
+
+
+/// Allocator that takes the thread id pool when it frees,
+/// so it deadlocks if a page is freed with the pool locked.
+#[derive(Clone, Copy)]
+struct Reentrant<'a>(&'a AtomicUsize);
+
+unsafe impl Allocator for Reentrant<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = per_thread_object::thread_stats();
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Global.deallocate(ptr, layout)
+    }
+}
+
+// keep it as the only test in this file,
+// because threads of other tests would hold the pages.
+#[test]
+fn test_shrink_on_exit_frees_outside_pool_lock() {
+    // take a low id before spawning.
+    let id = ThreadId::current().index();
+    let frees = AtomicUsize::new(0);
+
+    let tl: ThreadLocal<usize, Reentrant<'_>> = ThreadLocal::builder()
+        .threads(1)
+        .page_size(1)
+        .shrink_on_exit(true)
+        .allocator(Reentrant(&frees))
+        .build();
+
+    thread::scope(|s| {
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let threads = (0..8)
+            .map(|n| {
+                let (tl, ready_tx) = (&tl, ready_tx.clone());
+                let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+                let handle = s.spawn(move || {
+                    per_thread_object::stack_token!(token);
+                    tl.get_or_init(token, || n);
+                    ready_tx.send(()).unwrap();
+                    let _ = stop_rx.recv();
+                });
+
+                (stop_tx, handle)
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..8 {
+            ready_rx.recv().unwrap();
+        }
+
+        for (stop_tx, handle) in threads {
+            drop(stop_tx);
+            handle.join().unwrap();
+        }
+    });
+
+    // pages of 1, 2, 4.. slots needed for `id`, with one lock-free slot.
+    let pages = (usize::BITS - id.leading_zeros()) as usize;
+    assert_eq!(pages, tl.stats().pages);
+    assert!(frees.load(Ordering::Relaxed) > 0);
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "std")]
+#![cfg(not(feature = "loom"))]
+#![cfg(not(feature = "shuttle"))]
+
+use std::thread;
+use std::sync::Barrier;
+use per_thread_object::ThreadLocal;
+
+
+#[test]
+fn test_stats() {
+    let tl: ThreadLocal<u64> = ThreadLocal::with_threads(1);
+    let empty = tl.stats();
+
+    assert_eq!(1, empty.capacity);
+    assert_eq!(0, empty.threads);
+    assert_eq!(0, empty.fast_slots + empty.fallback_slots);
+    assert!(empty.reserved_bytes > 0);
+
+    let barrier = Barrier::new(5);
+
+    thread::scope(|s| {
+        let handles = (0..4)
+            .map(|i| {
+                let (tl, barrier) = (&tl, &barrier);
+                s.spawn(move || {
+                    per_thread_object::stack_token!(token);
+                    tl.get_or_init(token, || i);
+                    barrier.wait();
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        barrier.wait();
+
+        // only one thread can use the single lock-free slot
+        let stats = tl.stats();
+        assert_eq!(4, stats.threads);
+        assert_eq!(4, stats.fast_slots + stats.fallback_slots);
+        assert!(stats.fallback_slots >= 3);
+        assert!(stats.pages >= 1);
+        assert!(stats.reserved_bytes > empty.reserved_bytes);
+
+        barrier.wait();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+
+    let stats = tl.stats();
+    assert_eq!(0, stats.threads);
+    assert_eq!(0, stats.fast_slots + stats.fallback_slots);
+}
+
+#[test]
+fn test_thread_stats() {
+    per_thread_object::stack_token!(token);
+    let tl: ThreadLocal<()> = ThreadLocal::new();
+    tl.get_or_init(token, || ());
+
+    let stats = per_thread_object::thread_stats();
+    assert!(stats.max >= 1);
+    assert!(stats.free < stats.max);
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 #[cfg(not(feature = "loom"))]
 #[cfg(not(feature = "shuttle"))]
 mod loom {